@@ -1,9 +1,20 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
 use exchange::{
   bitmex::Bitmex,
   bybit::Bybit,
+  decimal::{Price, Quantity, RoundingStrategy},
+  heartbeat::Heartbeat,
+  order::{Request, Side},
+  traits::{BalancesManager, OrderManager, PairGenerator, PositionManager, StreamFactory},
+  ws::Event,
+  ErrorKind,
+};
+use futures::{
+  future::{join, join_all},
+  select, FutureExt, StreamExt,
 };
-use futures::{select, StreamExt};
-use utils::sigmoid;
+use utils::{connected, sigmoid};
 
 mod utils;
 
@@ -11,87 +22,203 @@ mod utils;
 async fn main() {
   tracing_subscriber::fmt().init();
 
-  let bybit = Bybit::new();
-  let mut bybit_order_book = bybit.watch_order_book("MATICUSDT").await.fuse();
-  let mut bybit_active_orders = bybit.watch_active_orders().await.fuse();
+  let bybit = Bybit::from_env().unwrap();
+  if let Err(e) = bybit.sync_clock().await {
+    tracing::error!("sync_clock: {}", e);
+  }
+  let mut bybit_order_book = bybit.watch_order_book("MATICUSDT");
+  let mut bybit_balances = bybit.watch_balances();
+  connected(&mut bybit_balances).await;
+  bybit.load_all_balances().await.unwrap();
 
-  let bitmex = Bitmex::new();
-  let mut bitmex_order_book = bitmex.watch_order_book("MATICUSDT").await.fuse();
-  let mut bitmex_active_orders = bitmex.watch_active_orders().await.fuse();
+  let bitmex = Arc::new(Bitmex::from_env().unwrap());
+  bitmex.load_pairs().await.unwrap();
+  let instrument = bitmex.get_pair("MATICUSDT").unwrap().instrument;
+  let mut bitmex_active_orders = bitmex.watch_active_orders();
+  let mut bitmex_positions = bitmex.watch_positions();
+  connected(&mut bitmex_positions).await;
+  bitmex.load_positions().await.unwrap();
+  // Only our own symbol, other strategies may be quoting on the same account.
+  if let Err(e) = bitmex.cancel_all_for_symbol("MATICUSDT").await {
+    tracing::error!("cancel_all_for_symbol: {}", e);
+  }
+  // Quotes are cancelled by BitMEX if we stop refreshing for a minute.
+  let heartbeat = Heartbeat::start(bitmex.clone(), Duration::from_secs(60))
+    .await
+    .unwrap();
+  let mut shutdown = Box::pin(tokio::signal::ctrl_c().fuse());
+  // Our resting quote per side, amended in place on every tick.
+  let mut quotes: HashMap<Side, String> = HashMap::new();
 
   let order_book = loop {
     match bybit_order_book.next().await.unwrap() {
-      Ok(ob) => {
+      Ok(Event::Message(ob)) => {
         tracing::info!("order_book: {:?}", ob);
         break ob;
       }
+      Ok(Event::Connected | Event::Reconnected) => {}
       Err(e) => {
         tracing::error!("error: {:?}", e);
       }
     }
   };
 
-  let mut price = (order_book.data.a.first().unwrap().0.parse::<f64>().unwrap()
-    + order_book.data.b.first().unwrap().0.parse::<f64>().unwrap())
-    / 2_f64;
-  let mut ask = order_book.data.a.first().unwrap().0.parse::<f64>().unwrap();
-  let mut bid = order_book.data.b.first().unwrap().0.parse::<f64>().unwrap();
+  let mut ask = order_book.asks.first().unwrap().price.to_f64();
+  let mut bid = order_book.bids.first().unwrap().price.to_f64();
+  let mut price = (ask + bid) / 2_f64;
 
   loop {
     select! {
       order_book = bybit_order_book.next() => {
-        bitmex.cancel_all_active_orders().await;
-        let order_book = order_book.unwrap().unwrap();
-        ask = match order_book.data.a.first() {
-          Some(ask) => ask.0.parse().unwrap(),
+        let order_book = match order_book {
+          Some(Ok(Event::Message(order_book))) => order_book,
+          Some(Ok(Event::Connected)) => continue,
+          Some(Ok(Event::Reconnected)) => {
+            tracing::warn!("bybit order book reconnected");
+            continue;
+          }
+          Some(Err(e)) => {
+            tracing::error!("error: {}", e);
+            continue;
+          }
+          None => break,
+        };
+        ask = match order_book.asks.first() {
+          Some(ask) => ask.price.to_f64(),
           None => ask,
         };
-        bid = match order_book.data.b.first() {
-          Some(bid) => bid.0.parse().unwrap(),
+        bid = match order_book.bids.first() {
+          Some(bid) => bid.price.to_f64(),
           None => bid,
         };
         price = 1_f64 * (ask + bid) / 8_f64 + 3_f64 * price / 4_f64;
         tracing::info!("price: {:?}", price);
         tracing::info!("ask: {:?}", ask);
         tracing::info!("bid: {:?}", bid);
-        let base_quantity = bybit.get_balances("MATIC").await
-          .unwrap().result.list.first().unwrap()
-          .coin.first().unwrap()
-          .wallet_balance.parse::<f64>().unwrap();
+        // Bybit leaves out coins without a balance.
+        let base_quantity = bybit.get_balance("MATIC").unwrap_or_default().to_f64();
         tracing::info!("base_quantity: {:?}", base_quantity);
-        let quote_quantity = bybit.get_balances("USDT").await
-          .unwrap().result.list.first().unwrap()
-          .coin.first().unwrap()
-          .wallet_balance.parse::<f64>().unwrap();
+        let quote_quantity = bybit.get_balance("USDT").unwrap_or_default().to_f64();
         tracing::info!("quote_quantity: {:?}", quote_quantity);
         let x = base_quantity * price - quote_quantity;
         let buy_quantity = base_quantity / price * sigmoid(x) / 4_f64;
         let sell_quantity = quote_quantity * sigmoid(-x) / 4_f64;
         tracing::info!("buy_quantity: {:?}", buy_quantity);
         tracing::info!("sell_quantity: {:?}", sell_quantity);
-        let buy_request = exchange::bitmex::SubmitRequest {
-          symbol: "MATICUSDT".to_string(),
-          side: "Buy".to_string(),
-          order_qty: format!("{:.0}", buy_quantity.floor() * 10_f64.powi(3)).to_string(),
-          price: format!("{:.4}", bid * 0.98_f64).to_string(),
+        // Post-only, a quote that would cross the spread is cancelled instead of taking.
+        let limit = |side: Side, price: f64, quantity: f64| -> exchange::Result<Request> {
+          Ok(
+            Request::limit(
+              "MATICUSDT",
+              side,
+              instrument.round_price(Price::from_f64(price)?, RoundingStrategy::MidpointNearestEven),
+              instrument.quantity_for(Quantity::from_f64(quantity)?),
+            )
+            .post_only(),
+          )
+        };
+        let requests = match (
+          limit(Side::Buy, bid * 0.98_f64, buy_quantity),
+          limit(Side::Sell, ask * 1.02_f64, sell_quantity),
+        ) {
+          (Ok(buy), Ok(sell)) => vec![buy, sell],
+          (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("requests: {}", e);
+            continue;
+          }
         };
-        let sell_request = exchange::bitmex::SubmitRequest {
-          symbol: "MATICUSDT".to_string(),
-          side: "Sell".to_string(),
-          order_qty: format!("{:.0}", sell_quantity.floor() * 10_f64.powi(3)).to_string(),
-          price: format!("{:.4}", ask * 1.02_f64).to_string(),
+        tracing::info!("requests: {:?}", requests);
+        // Sides without a quote are placed in one batch, the others amended in place.
+        let (replaces, submits): (Vec<_>, Vec<_>) = requests
+          .into_iter()
+          .partition(|request| quotes.contains_key(&request.side));
+        let sides: Vec<Side> = submits.iter().map(|request| request.side).collect();
+        let replaced = join_all(replaces.into_iter().map(|request| {
+          let side = request.side;
+          let order_id = quotes[&side].clone();
+          let bitmex = &bitmex;
+          async move {
+            let result = bitmex.replace(&order_id, request).await;
+            (side, order_id, result)
+          }
+        }));
+        let submitted = async {
+          if submits.is_empty() {
+            Vec::new()
+          } else {
+            bitmex.submit_requests(submits).await
+          }
         };
-        tracing::info!("buy_request: {:?}", buy_request);
-        tracing::info!("sell_request: {:?}", sell_request);
-        let buy_request_result = bitmex.submit_request(buy_request).await;
-        let sell_request_result = bitmex.submit_request(sell_request).await;
-        tracing::info!("buy_request_result: {:?}", buy_request_result.text().await);
-        tracing::info!("sell_request_result: {:?}", sell_request_result.text().await);
+        let (replaced, submitted) = join(replaced, submitted).await;
+        tracing::info!("replaced: {:?}", replaced);
+        tracing::info!("submitted: {:?}", submitted);
+        for (side, result) in sides.into_iter().zip(submitted) {
+          if let Ok(ack) = result {
+            quotes.insert(side, ack.order_id);
+          }
+        }
+        let pair = bitmex.get_pair("MATICUSDT");
+        for (side, order_id, result) in replaced {
+          match result {
+            Ok(ack) => {
+              quotes.insert(side, ack.order_id);
+            }
+            // A quote that is gone, e.g. because it filled, is placed anew on the next
+            // tick. Any other failure may have left it live, so it is retried instead.
+            Err(e)
+              if e.kind() == Some(ErrorKind::OrderNotFound)
+                || pair
+                  .as_ref()
+                  .is_some_and(|pair| !pair.orders.contains_key(&order_id)) =>
+            {
+              quotes.remove(&side);
+            }
+            Err(_) => {}
+          }
+        }
       },
       active_orders = bitmex_active_orders.next() => {
-        let active_orders = active_orders.unwrap();
-        tracing::info!("active_orders: {:?}", active_orders);
+        match active_orders {
+          Some(Ok(active_orders)) => {
+            tracing::info!("active_orders: {:?}", active_orders);
+            if let Some(pair) = bitmex.get_pair("MATICUSDT") {
+              tracing::info!("live orders: {:?}", pair.live_orders().collect::<Vec<_>>());
+            }
+          }
+          Some(Err(e)) => tracing::error!("error: {}", e),
+          None => break,
+        }
       },
+      position = bitmex_positions.next() => {
+        match position {
+          Some(Ok(Event::Message(position))) => tracing::info!("position: {:?}", position),
+          Some(Ok(Event::Connected | Event::Reconnected)) => {}
+          Some(Err(e)) => tracing::error!("error: {}", e),
+          None => break,
+        }
+      },
+      balance = bybit_balances.next() => {
+        match balance {
+          Some(Ok(Event::Message(balance))) => tracing::info!("balance: {:?}", balance),
+          Some(Ok(Event::Connected)) => {}
+          // Changes made while disconnected are only in a fresh snapshot.
+          Some(Ok(Event::Reconnected)) => {
+            if let Err(e) = bybit.load_all_balances().await {
+              tracing::error!("load_all_balances: {}", e);
+            }
+          }
+          Some(Err(e)) => tracing::error!("error: {}", e),
+          None => break,
+        }
+      },
+      _ = shutdown => break,
     }
   }
+
+  if let Err(e) = bitmex.cancel_all_for_symbol("MATICUSDT").await {
+    tracing::error!("cancel_all_for_symbol: {}", e);
+  }
+  if let Err(e) = heartbeat.stop().await {
+    tracing::error!("heartbeat: {}", e);
+  }
 }
@@ -1,3 +1,18 @@
+use exchange::{traits::BoxStream, ws::Event};
+use futures::StreamExt;
+
 pub fn sigmoid(x: f64) -> f64 {
   1.0 / (1.0 + (-x).exp())
 }
+
+/// Polls `stream` until its first connection has sent its subscriptions, so a snapshot
+/// taken afterwards misses at most the updates of the venue's acknowledgement delay.
+pub async fn connected<T>(stream: &mut BoxStream<Event<T>>) {
+  while let Some(event) = stream.next().await {
+    match event {
+      Ok(Event::Connected) => return,
+      Ok(_) => {}
+      Err(e) => tracing::error!("error: {}", e),
+    }
+  }
+}
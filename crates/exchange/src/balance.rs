@@ -0,0 +1,41 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
+
+use crate::decimal::Quantity;
+
+/// Wallet balances of an adapter keyed by currency, shared with the streams that keep them
+/// current.
+pub(crate) type Balances = Arc<RwLock<HashMap<String, Quantity>>>;
+
+/// The wallet balance of one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Balance {
+  pub currency: String,
+  pub quantity: Quantity,
+}
+
+/// Replaces every cached balance with `snapshot`.
+pub(crate) fn reset(balances: &Balances, snapshot: impl IntoIterator<Item = Balance>) {
+  let mut balances = balances.write().expect("balances lock poisoned");
+  *balances = snapshot
+    .into_iter()
+    .map(|balance| (balance.currency, balance.quantity))
+    .collect();
+}
+
+pub(crate) fn update(balances: &Balances, balance: &Balance) {
+  balances
+    .write()
+    .expect("balances lock poisoned")
+    .insert(balance.currency.clone(), balance.quantity);
+}
+
+pub(crate) fn get(balances: &Balances, currency: &str) -> Option<Quantity> {
+  balances
+    .read()
+    .expect("balances lock poisoned")
+    .get(currency)
+    .copied()
+}
@@ -0,0 +1,145 @@
+use std::{collections::HashMap, future::Future, hash::Hash, sync::Arc};
+
+use futures::future::join_all;
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Sends the items that pass `check` in chunks of at most `size` through `send`, running the
+/// chunks concurrently. Returns one result per item, in input order. A chunk that fails as a
+/// whole fails each of its items with the shared error.
+pub(crate) async fn in_batches<'a, T, U, C, F, Fut>(
+  items: &'a [T],
+  size: usize,
+  check: C,
+  send: F,
+) -> Vec<Result<U>>
+where
+  C: Fn(&T) -> Result<()>,
+  F: Fn(Vec<&'a T>) -> Fut,
+  Fut: Future<Output = Result<Vec<Result<U>>>>,
+{
+  in_grouped_batches(items, size, |_| (), check, send).await
+}
+
+/// Like [`in_batches`], but only items of the same `group` share a chunk, for venues whose
+/// batch calls are scoped, e.g. to a product line.
+pub(crate) async fn in_grouped_batches<'a, T, U, K, G, C, F, Fut>(
+  items: &'a [T],
+  size: usize,
+  group: G,
+  check: C,
+  send: F,
+) -> Vec<Result<U>>
+where
+  K: Eq + Hash,
+  G: Fn(&T) -> K,
+  C: Fn(&T) -> Result<()>,
+  F: Fn(Vec<&'a T>) -> Fut,
+  Fut: Future<Output = Result<Vec<Result<U>>>>,
+{
+  let mut results: Vec<Option<Result<U>>> = Vec::with_capacity(items.len());
+  // Groups in order of first appearance, so chunks go out in a stable order.
+  let mut groups: Vec<Vec<usize>> = Vec::new();
+  let mut group_of: HashMap<K, usize> = HashMap::new();
+  for (index, item) in items.iter().enumerate() {
+    match check(item) {
+      Ok(()) => {
+        let next = groups.len();
+        let slot = *group_of.entry(group(item)).or_insert(next);
+        if slot == next {
+          groups.push(Vec::new());
+        }
+        groups[slot].push(index);
+        results.push(None);
+      }
+      Err(e) => results.push(Some(Err(e))),
+    }
+  }
+
+  let chunks: Vec<&[usize]> = groups
+    .iter()
+    .flat_map(|group| group.chunks(size.max(1)))
+    .collect();
+  let replies = join_all(
+    chunks
+      .iter()
+      .map(|chunk| send(chunk.iter().map(|&index| &items[index]).collect())),
+  )
+  .await;
+
+  for (chunk, reply) in chunks.into_iter().zip(replies) {
+    match reply {
+      Ok(replies) if replies.len() == chunk.len() => {
+        for (&index, reply) in chunk.iter().zip(replies) {
+          results[index] = Some(reply);
+        }
+      }
+      Ok(replies) => {
+        let error = Arc::new(Error::exchange(
+          ErrorKind::Unknown,
+          format!("{} replies to a batch of {}", replies.len(), chunk.len()),
+        ));
+        for &index in chunk {
+          results[index] = Some(Err(Error::Batch(error.clone())));
+        }
+      }
+      Err(e) if chunk.len() == 1 => results[chunk[0]] = Some(Err(e)),
+      Err(e) => {
+        let error = Arc::new(e);
+        for &index in chunk {
+          results[index] = Some(Err(Error::Batch(error.clone())));
+        }
+      }
+    }
+  }
+
+  results
+    .into_iter()
+    .map(|result| result.expect("every item has a result"))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn in_batches() {
+    let items = vec![1, -2, 3, 4, 5];
+    let check = |item: &i32| match *item > 0 {
+      true => Ok(()),
+      false => Err(Error::InvalidRequest(item.to_string())),
+    };
+    let results = super::in_batches(&items, 3, check, |chunk: Vec<&i32>| async move {
+      match chunk.contains(&&5) {
+        true => Err(Error::Auth("rejected".to_string())),
+        false => Ok(chunk.into_iter().map(|item| Ok(item * 10)).collect()),
+      }
+    })
+    .await;
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].as_ref().unwrap(), &10);
+    assert!(matches!(results[1], Err(Error::InvalidRequest(_))));
+    assert_eq!(results[2].as_ref().unwrap(), &30);
+    assert_eq!(results[3].as_ref().unwrap(), &40);
+    assert!(matches!(results[4], Err(Error::Auth(_))));
+  }
+
+  #[tokio::test]
+  async fn in_grouped_batches() {
+    let items = vec![1, 2, 3, 4, 5];
+    let results = super::in_grouped_batches(
+      &items,
+      2,
+      |item: &i32| item % 2,
+      |_| Ok(()),
+      |chunk: Vec<&i32>| async move {
+        assert!(chunk.iter().all(|item| *item % 2 == *chunk[0] % 2));
+        Ok(chunk.iter().map(|item| Ok(*item * 10)).collect())
+      },
+    )
+    .await;
+    let results: Vec<i32> = results.into_iter().map(Result::unwrap).collect();
+    assert_eq!(results, [10, 20, 30, 40, 50]);
+  }
+}
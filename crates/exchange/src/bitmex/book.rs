@@ -0,0 +1,219 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+
+use super::{OrderBookData, OrderBookResponse};
+use crate::{
+  book::{Applied, BookKeeper, LocalBook},
+  decimal::{Price, Quantity},
+  error::Result,
+  order::Side,
+};
+
+/// Messages held back while waiting for the `partial`, older ones are dropped first.
+const MAX_BUFFERED: usize = 1024;
+
+/// Which `orderBookL2` table to subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Depth {
+  /// `orderBookL2_25`, the 25 best levels per side.
+  #[default]
+  TwentyFive,
+  /// `orderBookL2`, every level.
+  Full,
+}
+
+impl Depth {
+  pub fn topic(self, symbol: &str) -> String {
+    match self {
+      Depth::TwentyFive => format!("orderBookL2_25:{}", symbol),
+      Depth::Full => format!("orderBookL2:{}", symbol),
+    }
+  }
+}
+
+/// Applies `partial`, `insert`, `update` and `delete` actions to a [`LocalBook`]. Levels are
+/// keyed by id, updates and deletes may leave out the price so it is remembered per id.
+#[derive(Debug, Default)]
+pub(super) struct OrderBookKeeper {
+  book: LocalBook,
+  levels: HashMap<u64, (Side, Price)>,
+  time: Option<DateTime<Utc>>,
+  partial: bool,
+  buffered: VecDeque<OrderBookResponse>,
+}
+
+impl BookKeeper for OrderBookKeeper {
+  type Message = OrderBookResponse;
+
+  fn reset(&mut self) {
+    self.book.clear();
+    self.levels.clear();
+    self.time = None;
+    self.partial = false;
+    self.buffered.clear();
+  }
+
+  fn apply(&mut self, response: OrderBookResponse) -> Result<Applied> {
+    match (response.action.as_str(), self.partial) {
+      ("partial", _) => {
+        let buffered = std::mem::take(&mut self.buffered);
+        self.reset();
+        self.partial = true;
+        if !self.apply_rows("insert", &response.data, None) {
+          return Ok(Applied::Gap);
+        }
+        // Anything that arrived ahead of the image and is not already part of it.
+        let snapshot = self.time;
+        for buffered in buffered {
+          if !self.apply_rows(&buffered.action, &buffered.data, snapshot) {
+            return Ok(Applied::Gap);
+          }
+        }
+      }
+      (_, false) => {
+        if self.buffered.len() == MAX_BUFFERED {
+          self.buffered.pop_front();
+        }
+        self.buffered.push_back(response);
+        return Ok(Applied::Skipped);
+      }
+      (action, true) => {
+        if !self.apply_rows(action, &response.data, None) {
+          return Ok(Applied::Gap);
+        }
+      }
+    }
+
+    if self.book.is_crossed() {
+      tracing::warn!("{} crossed after {}", response.table, response.action);
+      return Ok(Applied::Gap);
+    }
+    Ok(Applied::Updated(
+      self.book.to_order_book(self.time.unwrap_or_else(Utc::now)),
+    ))
+  }
+}
+
+impl OrderBookKeeper {
+  /// Applies the rows of one action, skipping those not newer than `after`. Returns false
+  /// when a row refers to a level the book does not know about.
+  fn apply_rows(
+    &mut self,
+    action: &str,
+    rows: &[OrderBookData],
+    after: Option<DateTime<Utc>>,
+  ) -> bool {
+    for row in rows {
+      if let (Some(after), Some(time)) = (after, row.timestamp) {
+        if time <= after {
+          continue;
+        }
+      }
+      match action {
+        "insert" => {
+          let Some(price) = row.price else {
+            tracing::warn!("level {} inserted without a price", row.id);
+            return false;
+          };
+          self.levels.insert(row.id, (row.side, price));
+          self
+            .book
+            .update(row.side, price, row.size.unwrap_or_default());
+        }
+        "update" => {
+          let Some(&(side, price)) = self.levels.get(&row.id) else {
+            tracing::warn!("update of unknown level {}", row.id);
+            return false;
+          };
+          self.book.update(side, price, row.size.unwrap_or_default());
+        }
+        "delete" => {
+          let Some((side, price)) = self.levels.remove(&row.id) else {
+            tracing::warn!("delete of unknown level {}", row.id);
+            return false;
+          };
+          self.book.update(side, price, Quantity::ZERO);
+        }
+        action => {
+          tracing::warn!("unknown book action {}", action);
+          return true;
+        }
+      }
+      self.time = self.time.max(row.timestamp);
+    }
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{error::decode, order::OrderBook};
+
+  fn message(action: &str, data: &str) -> OrderBookResponse {
+    decode(&format!(
+      r#"{{"table":"orderBookL2_25","action":"{}","data":{}}}"#,
+      action, data
+    ))
+    .unwrap()
+  }
+
+  fn book(applied: Applied) -> OrderBook {
+    match applied {
+      Applied::Updated(order_book) => order_book,
+      applied => panic!("expected a book, got {:?}", applied),
+    }
+  }
+
+  #[test]
+  fn partial_and_actions() {
+    let mut keeper = OrderBookKeeper::default();
+    let early = message(
+      "update",
+      r#"[{"symbol":"XBTUSD","id":1,"side":"Buy","size":300,"timestamp":"2023-12-20T10:00:01.000Z"}]"#,
+    );
+    let stale = message(
+      "update",
+      r#"[{"symbol":"XBTUSD","id":2,"side":"Sell","size":1,"timestamp":"2023-12-20T10:00:00.000Z"}]"#,
+    );
+    assert!(matches!(keeper.apply(early).unwrap(), Applied::Skipped));
+    assert!(matches!(keeper.apply(stale).unwrap(), Applied::Skipped));
+
+    let partial = message(
+      "partial",
+      r#"[
+        {"symbol":"XBTUSD","id":1,"side":"Buy","size":100,"price":42000.5,"timestamp":"2023-12-20T10:00:00.000Z"},
+        {"symbol":"XBTUSD","id":2,"side":"Sell","size":200,"price":42001.0,"timestamp":"2023-12-20T10:00:00.000Z"}
+      ]"#,
+    );
+    let order_book = book(keeper.apply(partial).unwrap());
+    assert_eq!(order_book.bids[0].quantity, 300.into());
+    assert_eq!(order_book.asks[0].quantity, 200.into());
+
+    let insert = message(
+      "insert",
+      r#"[{"symbol":"XBTUSD","id":3,"side":"Buy","size":50,"price":42000.0,"timestamp":"2023-12-20T10:00:02.000Z"}]"#,
+    );
+    assert_eq!(book(keeper.apply(insert).unwrap()).bids.len(), 2);
+
+    let delete = message(
+      "delete",
+      r#"[{"symbol":"XBTUSD","id":1,"side":"Buy","timestamp":"2023-12-20T10:00:03.000Z"}]"#,
+    );
+    let order_book = book(keeper.apply(delete).unwrap());
+    assert_eq!(order_book.bids.len(), 1);
+    assert_eq!(order_book.bids[0].price, 42000.into());
+  }
+
+  #[test]
+  fn unknown_level() {
+    let mut keeper = OrderBookKeeper::default();
+    keeper.apply(message("partial", "[]")).unwrap();
+    let update = message(
+      "update",
+      r#"[{"symbol":"XBTUSD","id":9,"side":"Sell","size":1,"timestamp":"2023-12-20T10:00:00.000Z"}]"#,
+    );
+    assert!(matches!(keeper.apply(update).unwrap(), Applied::Gap));
+  }
+}
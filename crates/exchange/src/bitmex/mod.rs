@@ -1,53 +1,218 @@
-use std::{collections::HashMap, env, pin::Pin};
+mod book;
 
+pub use book::Depth;
+
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+  time::Duration,
+};
+
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use futures::{stream, SinkExt, Stream, StreamExt};
-use hex::encode;
-use hmac::{Hmac, Mac};
-use reqwest::{header::HeaderMap, Response};
-use serde::{Deserialize, Serialize};
-use sha2::Sha256;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use url::Url;
-use uuid::Uuid;
-
-use crate::pair::Pair;
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{
+  balance::{self, Balance, Balances},
+  batch::in_batches,
+  book::order_book,
+  decimal::{Decimal, Price, Quantity},
+  environment::{EnvConfig, Environment},
+  error::{decode, Error, ErrorKind, Result},
+  ids::{self, client_order_id, SharedOrderIds},
+  instrument::{Instrument, Market},
+  order::{
+    Cancel, Fill, OrderAck, OrderBook, OrderRef, OrderState, OrderType, OrderUpdate, Request, Side,
+    TimeInForce, Trade,
+  },
+  pair::{self, Pair, Pairs},
+  position::{self, Position, Positions},
+  rate_limit::BitmexLimits,
+  rest::{HttpConfig, RestClient},
+  sign::{BitmexSigner, Signer},
+  traits::{
+    cancel_and_submit, BalancesManager, BoxStream, DeadManSwitch, FromApi, OrderManager,
+    PairGenerator, PositionManager, StreamFactory,
+  },
+  ws::{flat_messages, until_refused, Event, Protocol, Session},
+};
 
 pub struct Bitmex {
-  api_url: String,
+  rest: RestClient<BitmexSigner>,
   wss_url: String,
-  api_key: String,
-  secret_key: String,
-  to_internal_ids: HashMap<String, String>,
-  from_internal_ids: HashMap<String, String>,
-  pairs: HashMap<(String, String), Pair>,
-  balances: HashMap<String, f64>,
+  book_depth: Depth,
+  ids: SharedOrderIds,
+  pairs: Pairs,
+  balances: Balances,
+  positions: Positions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum OrdType {
+  Limit,
+  Market,
+  StopLimit,
+  /// A stop market order.
+  Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BitmexTimeInForce {
+  GoodTillCancel,
+  ImmediateOrCancel,
+  FillOrKill,
+}
+
+/// Execution instructions, sent comma separated in `execInst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ExecInst {
+  /// Post-only, the order is cancelled if it would take liquidity.
+  ParticipateDoNotInitiate,
+  ReduceOnly,
+  /// Closes the position once triggered, implies `ReduceOnly`.
+  Close,
+}
+impl ExecInst {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Self::ParticipateDoNotInitiate => "ParticipateDoNotInitiate",
+      Self::ReduceOnly => "ReduceOnly",
+      Self::Close => "Close",
+    }
+  }
+
+  /// The `execInst` value for `instructions`, `None` if there are none.
+  pub fn join(instructions: &[Self]) -> Option<String> {
+    match instructions {
+      [] => None,
+      instructions => Some(
+        instructions
+          .iter()
+          .map(|instruction| instruction.as_str())
+          .collect::<Vec<_>>()
+          .join(","),
+      ),
+    }
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SubmitRequest {
+  #[serde(rename = "clOrdID")]
+  pub cl_ord_id: String,
   pub symbol: String,
-  pub side: String,
+  pub side: Side,
   pub order_qty: String,
-  pub price: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub price: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stop_px: Option<String>,
+  pub ord_type: OrdType,
+  pub time_in_force: BitmexTimeInForce,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub exec_inst: Option<String>,
 }
 impl SubmitRequest {
   pub fn new(
     symbol: impl Into<String>,
-    side: impl Into<String>,
+    side: Side,
     order_qty: impl Into<String>,
     price: impl Into<String>,
   ) -> Self {
     Self {
+      cl_ord_id: client_order_id(),
       symbol: symbol.into(),
-      side: side.into(),
+      side,
       order_qty: order_qty.into(),
-      price: price.into(),
+      price: Some(price.into()),
+      stop_px: None,
+      ord_type: OrdType::Limit,
+      time_in_force: BitmexTimeInForce::GoodTillCancel,
+      exec_inst: None,
+    }
+  }
+}
+impl From<&Request> for SubmitRequest {
+  fn from(request: &Request) -> Self {
+    // BitMEX has no post-only time in force, it is an execution instruction on a resting
+    // order.
+    let post_only = request.time_in_force == TimeInForce::PostOnly;
+    let exec_inst = [
+      (post_only, ExecInst::ParticipateDoNotInitiate),
+      (request.reduce_only, ExecInst::ReduceOnly),
+      (request.close_on_trigger, ExecInst::Close),
+    ]
+    .into_iter()
+    .filter_map(|(set, instruction)| set.then_some(instruction))
+    .collect::<Vec<_>>();
+    Self {
+      cl_ord_id: request.client_order_id.clone(),
+      symbol: request.symbol.clone(),
+      side: request.side,
+      order_qty: request.quantity.to_string(),
+      price: request.price.map(|price| price.to_string()),
+      stop_px: request.trigger_price.map(|price| price.to_string()),
+      ord_type: match request.order_type {
+        OrderType::Limit => OrdType::Limit,
+        OrderType::Market => OrdType::Market,
+        OrderType::StopLimit => OrdType::StopLimit,
+        OrderType::StopMarket => OrdType::Stop,
+      },
+      time_in_force: match request.time_in_force {
+        TimeInForce::GoodTillCancel | TimeInForce::PostOnly => BitmexTimeInForce::GoodTillCancel,
+        TimeInForce::ImmediateOrCancel => BitmexTimeInForce::ImmediateOrCancel,
+        TimeInForce::FillOrKill => BitmexTimeInForce::FillOrKill,
+      },
+      exec_inst: ExecInst::join(&exec_inst),
     }
   }
 }
 
+/// Amends an order addressed by `orderID` or `origClOrdID`. `orderQty` is the new total.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AmendRequest {
+  #[serde(rename = "orderID", skip_serializing_if = "Option::is_none")]
+  pub order_id: Option<String>,
+  #[serde(rename = "origClOrdID", skip_serializing_if = "Option::is_none")]
+  pub orig_cl_ord_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub order_qty: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub price: Option<String>,
+}
+impl AmendRequest {
+  /// Amends `order_id` to the price and quantity of `request`, by `origClOrdID` if `ours`.
+  pub fn for_order(order_id: &str, ours: bool, request: &Request) -> Self {
+    Self {
+      order_id: (!ours).then(|| order_id.to_string()),
+      orig_cl_ord_id: ours.then(|| order_id.to_string()),
+      order_qty: Some(request.quantity.to_string()),
+      price: request.price.map(|price| price.to_string()),
+    }
+  }
+}
+
+/// Orders per bulk placement or cancel call.
+const BATCH_SIZE: usize = 10;
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BulkRequest {
+  pub orders: Vec<SubmitRequest>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CancelRequest {
+  #[serde(rename = "orderID", skip_serializing_if = "Vec::is_empty")]
+  pub order_id: Vec<String>,
+  #[serde(rename = "clOrdID", skip_serializing_if = "Vec::is_empty")]
+  pub cl_ord_id: Vec<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetBalancesRequest {
@@ -64,25 +229,92 @@ impl GetBalancesRequest {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelAllRequest {
-  category: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  symbol: Option<String>,
 }
 impl CancelAllRequest {
   pub fn new() -> Self {
+    Self { symbol: None }
+  }
+
+  pub fn for_symbol(symbol: impl Into<String>) -> Self {
+    Self {
+      symbol: Some(symbol.into()),
+    }
+  }
+}
+impl Default for CancelAllRequest {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelAllAfterRequest {
+  /// Milliseconds until every order is cancelled, `0` disarms.
+  timeout: u64,
+}
+impl CancelAllAfterRequest {
+  pub fn new(timeout: Duration) -> Self {
     Self {
-      category: "spot".to_string(),
+      timeout: timeout.as_millis() as u64,
     }
   }
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstrumentData {
+  pub symbol: String,
+  pub state: String,
+  pub underlying: String,
+  pub quote_currency: String,
+  pub tick_size: Price,
+  pub lot_size: Option<Quantity>,
+  pub underlying_to_position_multiplier: Option<Decimal>,
+  /// Instrument type as a CFI code.
+  #[serde(default)]
+  pub typ: String,
+  #[serde(default)]
+  pub is_inverse: bool,
+}
+
+impl From<InstrumentData> for Pair {
+  fn from(data: InstrumentData) -> Self {
+    // Contracts are sized in `1 / underlyingToPositionMultiplier` of the underlying.
+    let contract_size = match data.underlying_to_position_multiplier {
+      Some(multiplier) if !multiplier.is_zero() => Decimal::ONE / multiplier,
+      _ => Decimal::ONE,
+    };
+    let lot_size = data.lot_size.unwrap_or(1.into());
+    let instrument = Instrument {
+      symbol: data.symbol,
+      tick_size: data.tick_size,
+      lot_size,
+      min_quantity: lot_size,
+      min_notional: None,
+      contract_size,
+      market: match (data.typ.as_str(), data.is_inverse) {
+        ("IFXXXP", _) => Market::Spot,
+        (_, true) => Market::Inverse,
+        (_, false) => Market::Linear,
+      },
+    };
+    Pair::new(data.underlying, data.quote_currency, instrument)
+  }
+}
+
+/// A row of the `orderBookL2` tables. Updates and deletes may leave out the price.
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderBookData {
-  pub timestamp: DateTime<Utc>,
   pub symbol: String,
-  pub bid_size: i32,
-  pub bid_price: f64,
-  pub ask_price: f64,
-  pub ask_size: i32,
+  pub id: u64,
+  pub side: Side,
+  pub size: Option<Quantity>,
+  pub price: Option<Price>,
+  pub timestamp: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -94,7 +326,30 @@ pub struct OrderBookResponse {
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct PriceVolumePair(String, String);
+pub struct PriceVolumePair(pub Price, pub Quantity);
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradesResponse {
+  pub table: String,
+  pub action: String,
+  pub data: Vec<TradeData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeData {
+  pub timestamp: DateTime<Utc>,
+  pub symbol: String,
+  pub side: Side,
+  /// In contracts.
+  pub size: Quantity,
+  /// `size` in the base asset.
+  pub home_notional: Quantity,
+  pub price: Price,
+  #[serde(rename = "trdMatchID")]
+  pub trd_match_id: String,
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
@@ -103,253 +358,1128 @@ enum WsRequestArg {
   Int(i64),
 }
 
+#[derive(Debug, Deserialize)]
+struct WsResponse {
+  table: Option<String>,
+  status: Option<i64>,
+  error: Option<String>,
+  /// Echo of the request an error answers.
+  request: Option<WsRequestEcho>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsRequestEcho {
+  op: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+  error: ErrorMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorMessage {
+  message: String,
+  name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Wallet {
+  pub account: i64,
+  pub currency: String,
+  pub amount: i64,
+  pub timestamp: Option<DateTime<Utc>>,
+}
+
+/// A row of the `margin` table. Updates only carry the fields that changed.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginData {
+  pub account: i64,
+  pub currency: String,
+  /// In the smallest unit of `currency`, including realised but unsettled PnL.
+  pub wallet_balance: Option<i64>,
+  pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl MarginData {
+  /// The balance this row reports, `None` for updates that leave it unchanged.
+  pub fn balance(&self) -> Option<Balance> {
+    let (currency, scale) = settlement_currency(&self.currency);
+    Some(Balance {
+      currency,
+      quantity: Decimal::new(self.wallet_balance?, scale).into(),
+    })
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginResponse {
+  pub table: String,
+  pub action: String,
+  pub data: Vec<MarginData>,
+}
+
+/// A row of the `position` table. Updates only carry the fields that changed besides the
+/// `account`, `symbol` and `currency` keys.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionData {
+  pub account: i64,
+  pub symbol: String,
+  pub currency: String,
+  pub current_qty: Option<Quantity>,
+  pub avg_entry_price: Option<Price>,
+  pub mark_price: Option<Price>,
+  /// In the smallest unit of `currency`.
+  pub unrealised_pnl: Option<i64>,
+  pub liquidation_price: Option<Price>,
+}
+
+impl PositionData {
+  /// Writes the fields this row carries to `position`.
+  pub fn apply(&self, position: &mut Position) {
+    if let Some(size) = self.current_qty {
+      position.size = size;
+    }
+    position.entry_price = self.avg_entry_price.or(position.entry_price);
+    position.mark_price = self.mark_price.or(position.mark_price);
+    position.liquidation_price = self.liquidation_price.or(position.liquidation_price);
+    if let Some(unrealised_pnl) = self.unrealised_pnl {
+      position.unrealised_pnl = Decimal::new(unrealised_pnl, settlement_currency(&self.currency).1);
+    }
+    // BitMEX nulls the prices of a closed position, which an update cannot tell apart from
+    // leaving them out.
+    if position.is_flat() {
+      position.entry_price = None;
+      position.liquidation_price = None;
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionsResponse {
+  pub table: String,
+  pub action: String,
+  pub data: Vec<PositionData>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct WsRequest {
   pub op: String,
   pub args: Vec<WsRequestArg>,
 }
+impl WsRequest {
+  fn message(op: &str, args: Vec<WsRequestArg>) -> Result<Message> {
+    let request = Self {
+      op: op.to_string(),
+      args,
+    };
+    let text = serde_json::to_string(&request).map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    Ok(Message::Text(text))
+  }
+}
+
+/// BitMEX framing for [`Session`]: `authKeyExpires` login, `subscribe` and a bare `ping`
+/// every 5s, which BitMEX answers with `pong`.
+#[derive(Clone)]
+struct BitmexProtocol {
+  signer: Option<BitmexSigner>,
+}
+
+impl Protocol for BitmexProtocol {
+  fn login(&self) -> Result<Option<Message>> {
+    let Some(signer) = &self.signer else {
+      return Ok(None);
+    };
+    let auth = signer.realtime_auth(signer.clock().now_millis());
+    let args = vec![
+      WsRequestArg::Str(auth.api_key),
+      WsRequestArg::Int(auth.expires),
+      WsRequestArg::Str(auth.signature),
+    ];
+    WsRequest::message("authKeyExpires", args).map(Some)
+  }
+
+  fn subscribe(&self, topics: &[String]) -> Result<Message> {
+    let args = topics.iter().cloned().map(WsRequestArg::Str).collect();
+    WsRequest::message("subscribe", args)
+  }
+
+  fn ping(&self) -> Result<Message> {
+    Ok(Message::Text("ping".to_string()))
+  }
+
+  fn ping_interval(&self) -> Duration {
+    Duration::from_secs(5)
+  }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ActiveOrdersResponse {
-    pub table: String,
-    pub action: String,
-    pub data: Vec<OrderData>,
+  pub table: String,
+  pub action: String,
+  pub data: Vec<OrderData>,
 }
 
+/// A row of the `order` table. Updates only carry the fields that changed.
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderData {
-    #[serde(rename = "orderID")]
-    pub order_id: String,
-    pub account: i64,
-    pub symbol: String,
-    pub side: String,
-    pub order_qty: f64,
-    pub price: f64,
-    pub display_qty: Option<f64>,
-    pub stop_px: Option<f64>,
-    pub peg_offset_value: Option<f64>,
-    pub currency: String,
-    pub ord_type: String,
-    pub time_in_force: String,
-    pub ord_status: String,
-    pub working_indicator: bool,
-    pub leaves_qty: f64,
-    pub cum_qty: f64,
-    pub avg_px: Option<f64>,
-    pub text: Option<String>,
-    pub transact_time: String,
-    pub timestamp: String,
+  #[serde(rename = "orderID")]
+  pub order_id: String,
+  #[serde(rename = "clOrdID")]
+  pub cl_ord_id: Option<String>,
+  pub account: Option<i64>,
+  pub symbol: Option<String>,
+  pub side: Option<Side>,
+  pub order_qty: Option<Quantity>,
+  pub price: Option<Price>,
+  pub display_qty: Option<Quantity>,
+  pub stop_px: Option<Price>,
+  pub peg_offset_value: Option<Price>,
+  pub currency: Option<String>,
+  pub ord_type: Option<String>,
+  pub time_in_force: Option<String>,
+  pub ord_status: Option<String>,
+  pub working_indicator: Option<bool>,
+  pub leaves_qty: Option<Quantity>,
+  pub cum_qty: Option<Quantity>,
+  pub avg_px: Option<Price>,
+  pub text: Option<String>,
+  /// Set on the rows of a cancel that could not be applied.
+  pub error: Option<String>,
+  pub transact_time: Option<String>,
+  pub timestamp: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionsResponse {
+  pub table: String,
+  pub action: String,
+  pub data: Vec<ExecutionData>,
+}
+
+/// A row of the `execution` table. Only trades carry the fill fields.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionData {
+  #[serde(rename = "execID")]
+  pub exec_id: String,
+  #[serde(rename = "orderID")]
+  pub order_id: String,
+  #[serde(rename = "clOrdID")]
+  pub cl_ord_id: Option<String>,
+  pub symbol: String,
+  pub side: Option<Side>,
+  pub exec_type: String,
+  pub last_qty: Option<Quantity>,
+  pub last_px: Option<Price>,
+  /// Fee in the smallest unit of `settl_currency`, negative for rebates.
+  pub exec_comm: Option<i64>,
+  pub settl_currency: Option<String>,
+  pub last_liquidity_ind: Option<String>,
+  pub timestamp: DateTime<Utc>,
 }
 
 impl Bitmex {
-  pub fn new() -> Self {
-    Self {
-      api_url: "https://www.bitmex.com".to_string(),
-      wss_url: "wss://ws.bitmex.com/realtime".to_string(),
-      api_key: env::var("BITMEX_API_KEY").unwrap(),
-      secret_key: env::var("BITMEX_SECRET_KEY").unwrap(),
-      to_internal_ids: HashMap::new(),
-      from_internal_ids: HashMap::new(),
-      pairs: HashMap::new(),
-      balances: HashMap::new(),
-    }
+  /// Connects to one of BitMEX's deployments, demo trading is its testnet.
+  pub fn from_environment(environment: Environment, api_key: &str, secret_key: &str) -> Self {
+    let (api_url, wss_url) = match environment {
+      Environment::Mainnet => ("https://www.bitmex.com", "wss://ws.bitmex.com/realtime"),
+      Environment::Testnet | Environment::Demo => (
+        "https://testnet.bitmex.com",
+        "wss://ws.testnet.bitmex.com/realtime",
+      ),
+    };
+    Self::from_api(api_url, wss_url, api_key, secret_key)
   }
 
-  pub async fn get_balances(&self, coin: &str) -> Response {
-    let query = GetBalancesRequest::new(coin);
-
-    let mut headers = HeaderMap::new();
-    let timestamp = Utc::now().timestamp();
-    let expires = timestamp + 60 * 60 * 24;
-    headers.append("api-expires", expires.to_string().parse().unwrap());
-    headers.append("api-key", self.api_key.parse().unwrap());
-    let qs = serde_qs::to_string(&query).unwrap();
-    let path = format!("/api/v1/user/wallet?{}", qs);
-    let param_str = format!("GET{}{}", path, expires);
-    tracing::info!("param_str: {}", param_str);
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(param_str.as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
-    headers.append("api-signature", signature.parse().unwrap());
-    headers.append("Content-Type", "application/json".parse().unwrap());
-
-    reqwest::Client::new()
-      .get(format!("{}{}", self.api_url, path))
-      .headers(headers)
+  /// Configures the adapter from `BITMEX_API_KEY`, `BITMEX_SECRET_KEY` and optionally
+  /// `BITMEX_ENVIRONMENT` or `BITMEX_API_URL` plus `BITMEX_WSS_URL`, `BITMEX_PROXY` and
+  /// `BITMEX_LOCAL_ADDRESS`.
+  pub fn from_env() -> Result<Self> {
+    let config = EnvConfig::read("BITMEX")?;
+    let adapter = match config.urls {
+      Some((api_url, wss_url)) => {
+        Self::from_api(&api_url, &wss_url, &config.api_key, &config.secret_key)
+      }
+      None => Self::from_environment(config.environment, &config.api_key, &config.secret_key),
+    };
+    adapter.with_http_config(&config.http)
+  }
+
+  pub async fn amend_order(&self, request: AmendRequest) -> Result<OrderData> {
+    let response = self
+      .rest
+      .put("/api/v1/order")
+      .json(&request)?
       .send()
-      .await
-      .unwrap()
-  }
-
-  pub async fn submit_request(&self, request: SubmitRequest) -> Response {
-    let mut headers = HeaderMap::new();
-    let timestamp = Utc::now().timestamp();
-    let expires = timestamp + 60 * 60 * 24;
-    headers.append("api-expires", expires.to_string().parse().unwrap());
-    headers.append("api-key", self.api_key.parse().unwrap());
-    let body = serde_json::to_string(&request).unwrap();
-    let path = format!("/api/v1/order");
-    let param_str = format!("POST{}{}{}", path, expires, body);
-    tracing::info!("param_str: {}", param_str);
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(param_str.as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
-    headers.append("api-signature", signature.parse().unwrap());
-    headers.append("Content-Type", "application/json".parse().unwrap());
-
-    reqwest::Client::new()
-      .post(format!("{}{}", self.api_url, path))
-      .headers(headers)
-      .body(body)
+      .await?;
+    decode_order_response(response).await
+  }
+
+  /// Places several orders in one call. Orders the venue refused come back `Rejected`.
+  pub async fn submit_bulk(&self, orders: Vec<SubmitRequest>) -> Result<Vec<OrderData>> {
+    let response = self
+      .rest
+      .post("/api/v1/order/bulk")
+      .json(&BulkRequest { orders })?
       .send()
-      .await
-      .unwrap()
-  }
-
-  pub async fn cancel_all_active_orders(&self) -> Response {
-    let mut headers = HeaderMap::new();
-    let timestamp = Utc::now().timestamp();
-    let expires = timestamp + 60 * 60 * 24;
-    headers.append("api-expires", expires.to_string().parse().unwrap());
-    headers.append("api-key", self.api_key.parse().unwrap());
-    let path = format!("/api/v1/order/all");
-    let param_str = format!("DELETE{}{}", path, expires);
-    tracing::info!("param_str: {}", param_str);
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(param_str.as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
-    headers.append("api-signature", signature.parse().unwrap());
-    headers.append("Content-Type", "application/json".parse().unwrap());
-
-    reqwest::Client::new()
-      .delete(format!("{}{}", self.api_url, path))
-      .headers(headers)
+      .await?;
+    decode_order_response(response).await
+  }
+
+  /// Cancels several orders in one call. Orders that could not be cancelled carry an
+  /// `error`.
+  pub async fn cancel(&self, request: CancelRequest) -> Result<Vec<OrderData>> {
+    let response = self
+      .rest
+      .delete("/api/v1/order")
+      .json(&request)?
       .send()
-      .await
-      .unwrap()
+      .await?;
+    decode_order_response(response).await
   }
 
-  pub async fn watch_order_book(
-    &self,
-    symbol: &str,
-  ) -> Pin<Box<dyn Stream<Item = Result<OrderBookResponse, String>>>> {
-    let (mut ws, _) = connect_async(Url::parse(self.wss_url.as_str()).unwrap())
-      .await
-      .unwrap();
+  /// Cancels the orders matched by `request`, returning their ids.
+  pub async fn cancel_all(&self, request: CancelAllRequest) -> Result<Vec<String>> {
+    let response = self
+      .rest
+      .delete("/api/v1/order/all")
+      .json(&request)?
+      .send()
+      .await?;
+    let orders: Vec<OrderData> = decode_order_response(response).await?;
+    let ids = self.ids.read().expect("order ids lock poisoned");
+    Ok(
+      orders
+        .into_iter()
+        .map(|order| match order.cl_ord_id.as_deref() {
+          None | Some("") => ids.internal(&order.order_id),
+          Some(cl_ord_id) => cl_ord_id.to_string(),
+        })
+        .collect(),
+    )
+  }
+
+  pub async fn cancel_all_after(&self, request: CancelAllAfterRequest) -> Result<()> {
+    let response = self
+      .rest
+      .post("/api/v1/order/cancelAllAfter")
+      .json(&request)?
+      .send()
+      .await?;
+    decode_response::<serde_json::Value>(response).await?;
+    Ok(())
+  }
+
+  /// Sets how long signatures stay valid, see [`BitmexSigner::with_expiry`]. The clock
+  /// follows the `Date` header of every reply.
+  pub fn with_expiry(mut self, expiry: Duration) -> Self {
+    self.rest = self.rest.map_signer(|signer| signer.with_expiry(expiry));
+    self
+  }
+
+  /// Sends REST calls through a client built from `config`.
+  pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+    self.rest = self.rest.with_http_config(config)?;
+    Ok(self)
+  }
+
+  /// Depth of the books kept by [`StreamFactory::watch_order_book`].
+  pub fn with_book_depth(mut self, depth: Depth) -> Self {
+    self.book_depth = depth;
+    self
+  }
+
+  pub async fn get_balances(&self, coin: &str) -> Result<Wallet> {
+    let response = self
+      .rest
+      .get("/api/v1/user/wallet")
+      .query(&GetBalancesRequest::new(coin))?
+      .send()
+      .await?;
+    decode_response(response).await
+  }
+
+  /// Margin of every currency when `currency` is `"all"`.
+  pub async fn get_margins(&self, currency: &str) -> Result<Vec<MarginData>> {
+    let response = self
+      .rest
+      .get("/api/v1/user/margin")
+      .query(&GetBalancesRequest::new(currency))?
+      .send()
+      .await?;
+    decode_response(response).await
+  }
+
+  pub async fn get_positions(&self) -> Result<Vec<PositionData>> {
+    let response = self.rest.get("/api/v1/position").send().await?;
+    decode_response(response).await
+  }
+
+  pub async fn get_instruments(&self) -> Result<Vec<InstrumentData>> {
+    let response = self.rest.get("/api/v1/instrument/active").send().await?;
+    decode_response(response).await
+  }
+
+  pub async fn submit_request(&self, request: SubmitRequest) -> Result<OrderData> {
+    let response = self
+      .rest
+      .post("/api/v1/order")
+      .json(&request)?
+      .send()
+      .await?;
+    decode_order_response(response).await
+  }
 
-    let request = WsRequest {
-      op: "subscribe".to_string(),
-      args: vec![WsRequestArg::Str(format!("quote:{}", symbol))],
+  fn private_session(&self, topics: Vec<String>) -> Session<BitmexProtocol> {
+    let protocol = BitmexProtocol {
+      signer: Some(self.rest.signer().clone()),
     };
+    Session::new(self.wss_url.clone(), protocol, topics)
+  }
+}
 
-    ws.send(Message::Text(serde_json::to_string(&request).unwrap()))
-      .await
-      .unwrap();
-
-    stream::unfold(ws, |mut ws| async {
-      match ws.next().await {
-        Some(Ok(Message::Text(text))) => {
-          tracing::info!("text: {}", text);
-          let x: OrderBookResponse = match serde_json::from_str(&text) {
-            Ok(x) => x,
-            Err(e) => {
-              tracing::error!("err: {}", e);
-              return Some((Err(e.to_string()), ws));
+#[async_trait]
+impl OrderManager for Bitmex {
+  async fn submit_requests(&self, requests: Vec<Request>) -> Vec<Result<OrderAck>> {
+    let check = |request: &Request| {
+      request.validate()?;
+      if let Some(pair) = self.get_pair(&request.symbol) {
+        pair.instrument.validate(request)?;
+      }
+      Ok(())
+    };
+    in_batches(
+      &requests,
+      BATCH_SIZE,
+      check,
+      |batch: Vec<&Request>| async move {
+        let orders = self
+          .submit_bulk(batch.iter().map(|&request| request.into()).collect())
+          .await?;
+        let mut ids = self.ids.write().expect("order ids lock poisoned");
+        Ok(
+          batch
+            .iter()
+            .map(|request| {
+              let order = find_order(&orders, &request.client_order_id)?;
+              if order.ord_status.as_deref() == Some("Rejected") {
+                let message = order.text.clone().unwrap_or_default();
+                return Err(Error::exchange(error_kind(&message), message));
+              }
+              ids.insert(request.client_order_id.as_str(), order.order_id.as_str());
+              Ok(OrderAck {
+                order_id: request.client_order_id.clone(),
+                exchange_order_id: order.order_id.clone(),
+              })
+            })
+            .collect(),
+        )
+      },
+    )
+    .await
+  }
+
+  async fn cancel_orders(&self, cancels: Vec<Cancel>) -> Vec<Result<String>> {
+    in_batches(
+      &cancels,
+      BATCH_SIZE,
+      |_| Ok(()),
+      |batch: Vec<&Cancel>| async move {
+        let mut request = CancelRequest::default();
+        {
+          let ids = self.ids.read().expect("order ids lock poisoned");
+          for cancel in &batch {
+            match ids.locate(&cancel.order) {
+              OrderRef::Client(id) => request.cl_ord_id.push(id),
+              order => request.order_id.push(order.id().to_string()),
             }
-          };
-          Some((Ok(x), ws))
-        }
-        Some(Ok(Message::Ping(x))) => {
-          tracing::info!("ping: {:?}", x);
-          ws.send(Message::Pong(x)).await.unwrap();
-          None
-        }
-        Some(Err(e)) => {
-          tracing::error!("err: {}", e);
-          Some((Err(e.to_string()), ws))
-        }
-        Some(x) => {
-          tracing::info!("other: {:?}", x);
-          None
+          }
         }
-        None => {
-          tracing::info!("none");
-          None
-        }
-      }
+        let orders = self.cancel(request).await?;
+        Ok(
+          batch
+            .iter()
+            .map(|cancel| {
+              let order = find_order(&orders, cancel.order_id())?;
+              match &order.error {
+                Some(message) => Err(Error::exchange(order_error_kind(message), message.clone())),
+                None => Ok(cancel.order_id().to_string()),
+              }
+            })
+            .collect(),
+        )
+      },
+    )
+    .await
+  }
+
+  async fn cancel_all_active_orders(&self) -> Result<Vec<String>> {
+    self.cancel_all(CancelAllRequest::new()).await
+  }
+
+  async fn cancel_all_for_symbol(&self, symbol: &str) -> Result<Vec<String>> {
+    self.cancel_all(CancelAllRequest::for_symbol(symbol)).await
+  }
+
+  async fn replace(&self, order_id: &str, request: Request) -> Result<OrderAck> {
+    if !pair::amendable(&self.pairs, order_id, &request) {
+      return cancel_and_submit(self, order_id, request).await;
+    }
+    request.validate()?;
+    if let Some(pair) = self.get_pair(&request.symbol) {
+      pair.instrument.validate(&request)?;
+    }
+    // Orders we placed are known by their `clOrdID`.
+    let ours = self
+      .ids
+      .read()
+      .expect("order ids lock poisoned")
+      .external(order_id)
+      .is_some();
+    let order = self
+      .amend_order(AmendRequest::for_order(order_id, ours, &request))
+      .await?;
+    Ok(OrderAck {
+      order_id: order_id.to_string(),
+      exchange_order_id: order.order_id,
     })
-    .boxed()
   }
+}
 
-  pub async fn watch_active_orders(
-    &self,
-  ) -> Pin<Box<dyn Stream<Item = Result<ActiveOrdersResponse, String>>>> {
-    let (mut ws, _) = connect_async(Url::parse(self.wss_url.as_str()).unwrap())
-      .await
-      .unwrap();
+impl FromApi for Bitmex {
+  fn from_api(api_url: &str, api_wss_url: &str, api_key: &str, secret_key: &str) -> Self {
+    Self {
+      rest: RestClient::new(api_url, BitmexSigner::new(api_key, secret_key))
+        .with_rate_limits(BitmexLimits),
+      wss_url: api_wss_url.to_string(),
+      book_depth: Depth::default(),
+      ids: SharedOrderIds::default(),
+      pairs: Arc::new(RwLock::new(HashMap::new())),
+      balances: Balances::default(),
+      positions: Positions::default(),
+    }
+  }
+}
 
-    let expires = Utc::now().timestamp() + 60 * 60 * 24;
+/// Balances are the margin table's wallet balances, which include realised PnL as soon as it
+/// is booked rather than at the next settlement.
+#[async_trait]
+impl BalancesManager for Bitmex {
+  async fn load_all_balances(&self) -> Result<()> {
+    let margins = self.get_margins("all").await?;
+    balance::reset(
+      &self.balances,
+      margins.iter().filter_map(MarginData::balance),
+    );
+    Ok(())
+  }
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(format!("GET/realtime{}", expires).as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
+  fn get_balance(&self, ticker: &str) -> Option<Quantity> {
+    balance::get(&self.balances, ticker)
+  }
+}
 
-    let auth_request = WsRequest {
-      op: "authKeyExpires".to_string(),
-      args: vec![
-        WsRequestArg::Str(self.api_key.clone()),
-        WsRequestArg::Int(expires),
-        WsRequestArg::Str(signature),
-      ],
-    };
+#[async_trait]
+impl PositionManager for Bitmex {
+  async fn load_positions(&self) -> Result<()> {
+    let rows = self.get_positions().await?;
+    position::reset(
+      &self.positions,
+      rows.iter().map(|row| {
+        let mut position = Position::flat(row.symbol.as_str());
+        row.apply(&mut position);
+        position
+      }),
+    );
+    Ok(())
+  }
 
-    ws.send(Message::Text(serde_json::to_string(&auth_request).unwrap()))
+  fn get_position(&self, symbol: &str) -> Option<Position> {
+    position::get(&self.positions, symbol)
+  }
+}
+
+/// Backed by `cancelAllAfter`, which BitMEX recommends refreshing every 15 seconds with a
+/// 60 second timeout.
+#[async_trait]
+impl DeadManSwitch for Bitmex {
+  async fn arm(&self, timeout: Duration) -> Result<()> {
+    self
+      .cancel_all_after(CancelAllAfterRequest::new(timeout))
+      .await
+  }
+
+  async fn disarm(&self) -> Result<()> {
+    self
+      .cancel_all_after(CancelAllAfterRequest::new(Duration::ZERO))
       .await
-      .unwrap();
+  }
+}
+
+#[async_trait]
+impl PairGenerator for Bitmex {
+  async fn load_pairs(&self) -> Result<()> {
+    let instruments = self.get_instruments().await?;
+    let pairs = instruments
+      .into_iter()
+      .map(|data| (data.symbol.clone(), Pair::from(data)));
+    self
+      .pairs
+      .write()
+      .expect("pairs lock poisoned")
+      .extend(pairs);
+    Ok(())
+  }
 
-    let request = WsRequest {
-      op: "subscribe".to_string(),
-      args: vec![WsRequestArg::Str("order".to_string())],
+  fn get_pair(&self, symbol: &str) -> Option<Pair> {
+    self
+      .pairs
+      .read()
+      .expect("pairs lock poisoned")
+      .get(symbol)
+      .cloned()
+  }
+}
+
+impl StreamFactory for Bitmex {
+  fn watch_order_book(&self, symbol: &str) -> BoxStream<Event<OrderBook>> {
+    let url = self.wss_url.clone();
+    let topic = self.book_depth.topic(symbol);
+    let subscribe = move || {
+      let session = Session::new(
+        url.clone(),
+        BitmexProtocol { signer: None },
+        vec![topic.clone()],
+      );
+      events::<OrderBookResponse>(session).boxed()
     };
+    // Levels are sized in contracts, converted once `load_pairs` has seen the symbol.
+    let pairs = self.pairs.clone();
+    let symbol = symbol.to_string();
+    let books = order_book::<book::OrderBookKeeper, _, _>(subscribe).map(move |event| {
+      event.map(|event| {
+        event.map(
+          |order_book| match pairs.read().expect("pairs lock poisoned").get(&symbol) {
+            Some(pair) => order_book.in_base(&pair.instrument),
+            None => order_book,
+          },
+        )
+      })
+    });
 
-    ws.send(Message::Text(serde_json::to_string(&request).unwrap()))
-      .await
-      .unwrap();
-
-    stream::unfold(ws, |mut ws| async {
-      match ws.next().await {
-        Some(Ok(Message::Text(text))) => {
-          tracing::info!("text: {}", text);
-          let x: ActiveOrdersResponse = match serde_json::from_str(&text) {
-            Ok(x) => x,
-            Err(e) => {
-              tracing::error!("err: {}", e);
-              return Some((Err("err".to_string()), ws));
-            }
-          };
-          Some((Ok(x), ws))
-        }
-        Some(Ok(Message::Ping(x))) => {
-          tracing::info!("ping: {:?}", x);
-          ws.send(Message::Pong(x)).await.unwrap();
-          Some((Err("pong".to_string()), ws))
-        }
-        Some(Err(e)) => {
-          tracing::error!("err: {}", e);
-          Some((Err("err".to_string()), ws))
-        }
-        Some(x) => {
-          tracing::info!("other: {:?}", x);
-          Some((Err("other".to_string()), ws))
-        }
-        None => {
-          tracing::info!("none");
-          None
+    Box::pin(books.fuse())
+  }
+
+  fn watch_trades(&self, symbol: &str) -> BoxStream<Event<Trade>> {
+    let session = Session::new(
+      self.wss_url.clone(),
+      BitmexProtocol { signer: None },
+      vec![format!("trade:{}", symbol)],
+    );
+    let trades = flat_messages(events::<TradesResponse>(session), |response| {
+      match response.action.as_str() {
+        // The last trades before subscribing, already seen after a reconnect.
+        "partial" => Vec::new(),
+        _ => response.data.iter().map(|trade| Ok(trade.into())).collect(),
+      }
+    });
+
+    Box::pin(trades.fuse())
+  }
+
+  fn watch_executions(&self) -> BoxStream<Event<Fill>> {
+    let ids = self.ids.clone();
+    let session = self.private_session(vec!["execution".to_string()]);
+    let fills = flat_messages(events::<ExecutionsResponse>(session), |response| {
+      match response.action.as_str() {
+        // Executions from before subscribing, already seen after a reconnect.
+        "partial" => Vec::new(),
+        _ => response
+          .data
+          .iter()
+          // Order acknowledgements, cancels and funding are not fills.
+          .filter(|execution| execution.exec_type == "Trade")
+          .map(Fill::try_from)
+          .collect(),
+      }
+    })
+    .map_ok(move |event| {
+      event.map(|mut fill| {
+        if fill.order_id == fill.exchange_order_id {
+          let ids = ids.read().expect("order ids lock poisoned");
+          fill.order_id = ids.internal(&fill.exchange_order_id);
         }
+        fill
+      })
+    });
+
+    Box::pin(fills.fuse())
+  }
+
+  fn watch_balances(&self) -> BoxStream<Event<Balance>> {
+    let balances = self.balances.clone();
+    let session = self.private_session(vec!["margin".to_string()]);
+    let updates = flat_messages(events::<MarginResponse>(session), |response| {
+      response
+        .data
+        .iter()
+        .filter_map(MarginData::balance)
+        .map(Ok)
+        .collect()
+    })
+    .map_ok(move |event| {
+      event.map(|balance| {
+        balance::update(&balances, &balance);
+        balance
+      })
+    });
+
+    Box::pin(updates.fuse())
+  }
+
+  fn watch_positions(&self) -> BoxStream<Event<Position>> {
+    let positions = self.positions.clone();
+    let session = self.private_session(vec!["position".to_string()]);
+    let updates = flat_messages(events::<PositionsResponse>(session), move |response| {
+      // The image sent on every subscribe replaces what we cached.
+      if response.action == "partial" {
+        position::reset(&positions, []);
       }
+      response
+        .data
+        .iter()
+        .map(|row| {
+          Ok(position::update(&positions, &row.symbol, |position| {
+            row.apply(position)
+          }))
+        })
+        .collect()
+    });
+
+    Box::pin(updates.fuse())
+  }
+
+  fn watch_active_orders(&self) -> BoxStream<Event<OrderUpdate>> {
+    let pairs = self.pairs.clone();
+    let tracked = self.pairs.clone();
+    let ids = self.ids.clone();
+    let session = self.private_session(vec!["order".to_string()]);
+    let updates = flat_messages(events::<ActiveOrdersResponse>(session), move |response| {
+      match response.action.as_str() {
+        // The image of open orders sent on every subscribe replaces what we tracked.
+        "partial" => pair::clear_orders(&pairs),
+        // Rows leave the table some time after their final update.
+        "delete" => return Vec::new(),
+        _ => {}
+      }
+      response.data.iter().map(OrderUpdate::try_from).collect()
     })
-    .boxed()
+    .map_ok(move |event| {
+      event.map(|mut update| {
+        ids::resolve(&ids, &mut update);
+        pair::track(&tracked, &update);
+        update
+      })
+    });
+
+    Box::pin(updates.fuse())
+  }
+}
+
+impl From<&TradeData> for Trade {
+  fn from(trade: &TradeData) -> Self {
+    Self {
+      time: trade.timestamp,
+      price: trade.price,
+      quantity: trade.home_notional,
+      aggressor_side: trade.side,
+      trade_id: trade.trd_match_id.clone(),
+    }
+  }
+}
+
+impl TryFrom<&ExecutionData> for Fill {
+  type Error = Error;
+
+  fn try_from(execution: &ExecutionData) -> Result<Self> {
+    let missing = |field: &str| Error::Decode {
+      source: serde::de::Error::custom(format!("execution without {}", field)),
+      payload: execution.exec_id.clone(),
+    };
+    let (fee_currency, scale) = settlement_currency(
+      execution
+        .settl_currency
+        .as_deref()
+        .ok_or_else(|| missing("settlCurrency"))?,
+    );
+    Ok(Self {
+      order_id: match execution.cl_ord_id.as_deref() {
+        None | Some("") => execution.order_id.clone(),
+        Some(cl_ord_id) => cl_ord_id.to_string(),
+      },
+      exchange_order_id: execution.order_id.clone(),
+      trade_id: execution.exec_id.clone(),
+      symbol: execution.symbol.clone(),
+      side: execution.side.ok_or_else(|| missing("side"))?,
+      time: execution.timestamp,
+      price: execution.last_px.ok_or_else(|| missing("lastPx"))?,
+      quantity: execution.last_qty.ok_or_else(|| missing("lastQty"))?,
+      fee: Decimal::new(execution.exec_comm.unwrap_or_default(), scale),
+      fee_currency,
+      is_maker: execution.last_liquidity_ind.as_deref() == Some("AddedLiquidity"),
+    })
+  }
+}
+
+/// The currency of a BitMEX settlement code and the decimals of its smallest unit, in which
+/// fees are reported.
+fn settlement_currency(code: &str) -> (String, u32) {
+  match code {
+    "XBt" => ("XBT".to_string(), 8),
+    "USDt" => ("USDT".to_string(), 6),
+    "Gwei" => ("ETH".to_string(), 9),
+    code => (code.to_uppercase(), 0),
+  }
+}
+
+impl TryFrom<&OrderData> for OrderUpdate {
+  type Error = Error;
+
+  fn try_from(order: &OrderData) -> Result<Self> {
+    let state = match order.ord_status.as_deref() {
+      None | Some("PendingCancel") | Some("PendingReplace") => None,
+      Some("PendingNew") => Some(OrderState::PendingNew),
+      Some("New") | Some("Untriggered") | Some("Triggered") => Some(OrderState::New),
+      Some("PartiallyFilled") => Some(OrderState::PartiallyFilled),
+      Some("Filled") => Some(OrderState::Filled),
+      Some("Canceled") | Some("Stopped") => Some(OrderState::Cancelled),
+      Some("Rejected") => Some(OrderState::Rejected),
+      Some("Expired") | Some("DoneForDay") => Some(OrderState::Expired),
+      Some(status) => {
+        return Err(Error::Decode {
+          source: serde::de::Error::custom("unknown order status"),
+          payload: status.to_string(),
+        })
+      }
+    };
+    let order_id = match order.cl_ord_id.as_deref() {
+      None | Some("") => order.order_id.clone(),
+      Some(cl_ord_id) => cl_ord_id.to_string(),
+    };
+    Ok(Self {
+      order_id,
+      exchange_order_id: order.order_id.clone(),
+      symbol: order.symbol.clone(),
+      side: order.side,
+      state,
+      price: order.price,
+      quantity: order.order_qty,
+      filled_quantity: order.cum_qty,
+      average_price: order.avg_px,
+    })
+  }
+}
+
+/// The row of a bulk reply that belongs to `id`, a client or venue order id.
+fn find_order<'a>(orders: &'a [OrderData], id: &str) -> Result<&'a OrderData> {
+  orders
+    .iter()
+    .find(|order| order.order_id == id || order.cl_ord_id.as_deref() == Some(id))
+    .ok_or_else(|| {
+      Error::exchange(
+        ErrorKind::Unknown,
+        format!("order {} is missing from the reply", id),
+      )
+    })
+}
+
+/// Decodes a REST reply, turning BitMEX's `{"error": {"message", "name"}}` bodies into errors.
+async fn decode_response<T: DeserializeOwned>(response: Response) -> Result<T> {
+  decode_reply(response, error_kind).await
+}
+
+/// [`decode_response`] for the order endpoints, see [`order_error_kind`].
+async fn decode_order_response<T: DeserializeOwned>(response: Response) -> Result<T> {
+  decode_reply(response, order_error_kind).await
+}
+
+async fn decode_reply<T: DeserializeOwned>(
+  response: Response,
+  kind_of: fn(&str) -> ErrorKind,
+) -> Result<T> {
+  let status = response.status();
+  let retry_after = response
+    .headers()
+    .get(RETRY_AFTER)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse().ok())
+    .map(Duration::from_secs);
+  let text = response.text().await?;
+  tracing::info!("response: {} {}", status, text);
+
+  if status.is_success() {
+    return decode(&text);
+  }
+  let message = match decode::<ErrorResponse>(&text) {
+    Ok(response) => format!("{}: {}", response.error.name, response.error.message),
+    Err(_) => text,
+  };
+  let kind = match kind_of(&message) {
+    ErrorKind::Unknown => ErrorKind::from_status(status),
+    kind => kind,
+  };
+  match status {
+    StatusCode::TOO_MANY_REQUESTS => Err(Error::RateLimited { retry_after }),
+    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(Error::Auth(message)),
+    status => Err(Error::Exchange {
+      code: status.as_u16().into(),
+      kind,
+      message,
+    }),
+  }
+}
+
+/// BitMEX rejects with messages rather than codes, this matches the ones worth telling
+/// apart.
+fn error_kind(message: &str) -> ErrorKind {
+  let message = message.to_lowercase();
+  let contains = |needles: &[&str]| needles.iter().any(|needle| message.contains(needle));
+  match () {
+    _ if contains(&["insufficient available balance", "insufficient margin"]) => {
+      ErrorKind::InsufficientBalance
+    }
+    _ if contains(&["duplicate clordid"]) => ErrorKind::DuplicateClientId,
+    _ if contains(&["ticksize", "invalid price", "price must be"]) => ErrorKind::InvalidPrice,
+    _ if contains(&["lotsize", "invalid orderqty", "order quantity"]) => ErrorKind::InvalidQuantity,
+    _ if contains(&["this request has expired", "api-expires"]) => ErrorKind::InvalidTimestamp,
+    _ if contains(&["overloaded", "try again later"]) => ErrorKind::SystemBusy,
+    _ => ErrorKind::Unknown,
+  }
+}
+
+/// Like [`error_kind`], for replies of the order endpoints, where an unknown order id means
+/// the order is gone.
+fn order_error_kind(message: &str) -> ErrorKind {
+  let lowercase = message.to_lowercase();
+  let unknown = [
+    "invalid orderid",
+    "invalid origclordid",
+    "not found or not owned",
+  ];
+  if unknown.iter().any(|needle| lowercase.contains(needle)) {
+    ErrorKind::OrderNotFound
+  } else {
+    error_kind(message)
+  }
+}
+
+/// Decodes the table messages of a session, skipping acknowledgements and pongs.
+fn events<T: DeserializeOwned + Send + 'static>(
+  session: Session<BitmexProtocol>,
+) -> impl Stream<Item = Result<Event<T>>> + Send {
+  until_refused(session.into_stream().try_filter_map(|event| async move {
+    match event {
+      Event::Message(text) if text == "pong" => Ok(None),
+      Event::Message(text) => decode_ws_message(&text)
+        .transpose()
+        .map(|message| message.map(Event::Message)),
+      Event::Connected => Ok(Some(Event::Connected)),
+      Event::Reconnected => Ok(Some(Event::Reconnected)),
+    }
+  }))
+}
+
+fn decode_ws_message<T: DeserializeOwned>(text: &str) -> Option<Result<T>> {
+  let response: WsResponse = match decode(text) {
+    Ok(response) => response,
+    Err(e) => return Some(Err(e)),
+  };
+  match (response.table, response.error) {
+    (Some(_), _) => Some(decode(text)),
+    (None, Some(message)) => match response.status {
+      Some(401) | Some(403) => Some(Err(Error::Auth(message))),
+      _ if response
+        .request
+        .is_some_and(|request| request.op == "subscribe") =>
+      {
+        Some(Err(Error::Subscription(message)))
+      }
+      status => Some(Err(Error::Exchange {
+        code: status.unwrap_or(-1),
+        kind: error_kind(&message),
+        message,
+      })),
+    },
+    (None, None) => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn instrument() {
+    let data: InstrumentData = decode(
+      r#"{"symbol":"MATICUSDT","state":"Open","underlying":"MATIC","quoteCurrency":"USDT","tickSize":0.0001,"lotSize":1000,"underlyingToPositionMultiplier":1000}"#,
+    )
+    .unwrap();
+    let pair = Pair::from(data);
+    assert_eq!(pair.base, "MATIC");
+    assert_eq!(pair.instrument.tick_size, "0.0001".parse().unwrap());
+    assert_eq!(pair.instrument.contract_size, "0.001".parse().unwrap());
+    assert_eq!(
+      pair.instrument.quantity_for("12.3456".parse().unwrap()),
+      12000.into()
+    );
+  }
+
+  #[test]
+  fn order_update() {
+    let response: ActiveOrdersResponse = decode(
+      r#"{"table":"order","action":"update","data":[{"orderID":"b1c9","ordStatus":"PartiallyFilled","leavesQty":600,"cumQty":400,"avgPx":0.7001}]}"#,
+    )
+    .unwrap();
+    let update = OrderUpdate::try_from(&response.data[0]).unwrap();
+    assert_eq!(update.state, Some(OrderState::PartiallyFilled));
+    assert_eq!(update.filled_quantity, Some(400.into()));
+    assert_eq!(update.symbol, None);
+  }
+
+  #[test]
+  fn trade() {
+    let response: TradesResponse = decode(
+      r#"{"table":"trade","action":"insert","data":[{"timestamp":"2023-12-19T22:24:49.167Z","symbol":"MATICUSDT","side":"Sell","size":2000,"price":0.8123,"tickDirection":"MinusTick","trdMatchID":"00000000-006d-1000-0000-000a3c4a1f8e","grossValue":1624600,"homeNotional":2,"foreignNotional":1.6246}]}"#,
+    )
+    .unwrap();
+    let trade = Trade::from(&response.data[0]);
+    assert_eq!(trade.aggressor_side, Side::Sell);
+    assert_eq!(trade.price, "0.8123".parse().unwrap());
+    assert_eq!(trade.quantity, 2.into());
+    assert_eq!(trade.trade_id, "00000000-006d-1000-0000-000a3c4a1f8e");
+  }
+
+  #[test]
+  fn execution() {
+    let response: ExecutionsResponse = decode(
+      r#"{"table":"execution","action":"insert","data":[{"execID":"e1","orderID":"b1c9","clOrdID":"1","symbol":"MATICUSDT","side":"Buy","lastQty":1000,"lastPx":0.8,"execType":"Trade","execComm":-120,"settlCurrency":"USDt","lastLiquidityInd":"AddedLiquidity","timestamp":"2023-12-19T22:24:49.167Z"}]}"#,
+    )
+    .unwrap();
+    let fill = Fill::try_from(&response.data[0]).unwrap();
+    assert_eq!(fill.order_id, "1");
+    assert_eq!(fill.fee, "-0.00012".parse().unwrap());
+    assert_eq!(fill.fee_currency, "USDT");
+    assert!(fill.is_maker);
+  }
+
+  #[test]
+  fn margin() {
+    let response: MarginResponse = decode(
+      r#"{"table":"margin","action":"update","data":[{"account":1,"currency":"USDt","walletBalance":1250000000,"timestamp":"2023-12-19T22:24:49.167Z"},{"account":1,"currency":"XBt","marginBalance":100}]}"#,
+    )
+    .unwrap();
+    let balance = response.data[0].balance().unwrap();
+    assert_eq!(balance.currency, "USDT");
+    assert_eq!(balance.quantity, 1250.into());
+    assert_eq!(response.data[1].balance(), None);
+  }
+
+  #[test]
+  fn position() {
+    let response: PositionsResponse = decode(
+      r#"{"table":"position","action":"update","data":[{"account":1,"symbol":"MATICUSDT","currency":"USDt","currentQty":-3000,"avgEntryPrice":0.81,"markPrice":0.8,"unrealisedPnl":30000000,"liquidationPrice":1.2},{"account":1,"symbol":"MATICUSDT","currency":"USDt","markPrice":0.79},{"account":1,"symbol":"MATICUSDT","currency":"USDt","currentQty":0}]}"#,
+    )
+    .unwrap();
+    let mut position = Position::flat("MATICUSDT");
+    response.data[0].apply(&mut position);
+    response.data[1].apply(&mut position);
+    assert_eq!(position.size, (-3000).into());
+    assert_eq!(position.entry_price, Some("0.81".parse().unwrap()));
+    assert_eq!(position.mark_price, Some("0.79".parse().unwrap()));
+    assert_eq!(position.unrealised_pnl, 30.into());
+
+    response.data[2].apply(&mut position);
+    assert!(position.is_flat());
+    assert_eq!(position.entry_price, None);
+  }
+
+  #[test]
+  fn error_kinds() {
+    assert_eq!(
+      error_kind("Account has insufficient Available Balance, 20 XBt required"),
+      ErrorKind::InsufficientBalance
+    );
+    assert_eq!(
+      error_kind("Invalid price tickSize"),
+      ErrorKind::InvalidPrice
+    );
+    assert_eq!(
+      error_kind("Duplicate clOrdID"),
+      ErrorKind::DuplicateClientId
+    );
+    assert_eq!(
+      order_error_kind("Invalid orderID"),
+      ErrorKind::OrderNotFound
+    );
+    assert_eq!(error_kind("Invalid orderID"), ErrorKind::Unknown);
+    assert_eq!(order_error_kind("Instrument not found"), ErrorKind::Unknown);
+    assert_eq!(error_kind("Order expired"), ErrorKind::Unknown);
+    assert_eq!(
+      error_kind("The system is currently overloaded. Please try again later."),
+      ErrorKind::SystemBusy
+    );
+    assert_eq!(error_kind("Something else"), ErrorKind::Unknown);
+
+    let error = Error::Batch(Arc::new(Error::exchange(
+      error_kind("This request has expired - `expires` is in the past."),
+      "expired",
+    )));
+    assert!(error.is_retryable());
+  }
+
+  #[test]
+  fn submit_request() {
+    let request = Request::limit("MATICUSDT", Side::Buy, "0.7".parse().unwrap(), 1000.into())
+      .post_only()
+      .with_reduce_only(true);
+    let submit = SubmitRequest::from(&request);
+    assert_eq!(submit.ord_type, OrdType::Limit);
+    assert_eq!(submit.time_in_force, BitmexTimeInForce::GoodTillCancel);
+    assert_eq!(
+      submit.exec_inst.as_deref(),
+      Some("ParticipateDoNotInitiate,ReduceOnly")
+    );
+
+    let request =
+      Request::stop_market("MATICUSDT", Side::Sell, "0.6".parse().unwrap(), 1000.into())
+        .with_close_on_trigger(true);
+    let submit = serde_json::to_value(SubmitRequest::from(&request)).unwrap();
+    assert_eq!(submit["ordType"], "Stop");
+    assert_eq!(submit["side"], "Sell");
+    assert_eq!(submit["stopPx"], "0.6");
+    assert_eq!(submit["execInst"], "Close");
+    assert!(submit.get("price").is_none());
+  }
+
+  #[test]
+  fn amend_request() {
+    let request = Request::limit("MATICUSDT", Side::Buy, "0.7".parse().unwrap(), 1000.into());
+    let amend = serde_json::to_value(AmendRequest::for_order("ours", true, &request)).unwrap();
+    assert_eq!(amend["origClOrdID"], "ours");
+    assert!(amend.get("orderID").is_none());
+    assert_eq!(amend["orderQty"], "1000");
+
+    let amend = serde_json::to_value(AmendRequest::for_order("b1c9", false, &request)).unwrap();
+    assert_eq!(amend["orderID"], "b1c9");
+    assert!(amend.get("origClOrdID").is_none());
   }
 }
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use futures::{stream, Stream, StreamExt};
+
+use crate::{
+  decimal::{Price, Quantity},
+  error::Result,
+  order::{OrderBook, OrderBookEntry, Side},
+  ws::Event,
+};
+
+/// Full-depth book maintained from venue snapshots and incremental updates. Both sides are
+/// kept sorted best price first.
+#[derive(Debug, Clone, Default)]
+pub struct LocalBook {
+  bids: Vec<OrderBookEntry>,
+  asks: Vec<OrderBookEntry>,
+}
+
+impl LocalBook {
+  pub fn clear(&mut self) {
+    self.bids.clear();
+    self.asks.clear();
+  }
+
+  /// Sets the quantity resting at `price`, removing the level when it drops to zero.
+  pub fn update(&mut self, side: Side, price: Price, quantity: Quantity) {
+    let levels = match side {
+      Side::Buy => &mut self.bids,
+      Side::Sell => &mut self.asks,
+    };
+    let position = levels.binary_search_by(|level| match side {
+      Side::Buy => price.cmp(&level.price),
+      Side::Sell => level.price.cmp(&price),
+    });
+    match (position, quantity.is_zero()) {
+      (Ok(index), true) => {
+        levels.remove(index);
+      }
+      (Ok(index), false) => levels[index].quantity = quantity,
+      (Err(_), true) => {}
+      (Err(index), false) => levels.insert(index, OrderBookEntry { price, quantity }),
+    }
+  }
+
+  pub fn bids(&self) -> &[OrderBookEntry] {
+    &self.bids
+  }
+
+  pub fn asks(&self) -> &[OrderBookEntry] {
+    &self.asks
+  }
+
+  /// A book whose best bid is not below its best ask was fed inconsistent updates.
+  pub fn is_crossed(&self) -> bool {
+    match (self.bids.first(), self.asks.first()) {
+      (Some(bid), Some(ask)) => bid.price >= ask.price,
+      _ => false,
+    }
+  }
+
+  pub fn to_order_book(&self, time: DateTime<Utc>) -> OrderBook {
+    OrderBook {
+      time,
+      bids: self.bids.clone(),
+      asks: self.asks.clone(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub(crate) enum Applied {
+  Updated(OrderBook),
+  /// The message did not produce a complete book, e.g. it arrived before the first
+  /// snapshot.
+  Skipped,
+  /// An update went missing, the book has to be rebuilt from a fresh snapshot.
+  Gap,
+}
+
+/// Venue specific bookkeeping that turns book messages into [`LocalBook`] updates.
+pub(crate) trait BookKeeper: Default + Send {
+  type Message;
+
+  fn reset(&mut self);
+  fn apply(&mut self, message: Self::Message) -> Result<Applied>;
+}
+
+/// Maintains a book from the messages of `subscribe()`. On a gap the subscription is
+/// replaced by a fresh one, which starts with a snapshot, and `Reconnected` is emitted in
+/// place of its `Connected`.
+pub(crate) fn order_book<K, S, F>(
+  subscribe: F,
+) -> impl Stream<Item = Result<Event<OrderBook>>> + Send
+where
+  K: BookKeeper,
+  S: Stream<Item = Result<Event<K::Message>>> + Send + Unpin,
+  F: Fn() -> S + Send,
+{
+  let state = (subscribe(), K::default(), subscribe, false);
+  stream::unfold(
+    state,
+    |(mut events, mut keeper, subscribe, mut connected)| async move {
+      loop {
+        let item = match events.next().await? {
+          Ok(Event::Message(message)) => match keeper.apply(message) {
+            Ok(Applied::Updated(order_book)) => Ok(Event::Message(order_book)),
+            Ok(Applied::Skipped) => continue,
+            Ok(Applied::Gap) => {
+              keeper.reset();
+              events = subscribe();
+              Ok(Event::Reconnected)
+            }
+            Err(e) => Err(e),
+          },
+          Ok(Event::Connected) if connected => continue,
+          Ok(Event::Connected) => {
+            connected = true;
+            Ok(Event::Connected)
+          }
+          Ok(Event::Reconnected) => {
+            keeper.reset();
+            Ok(Event::Reconnected)
+          }
+          Err(e) => Err(e),
+        };
+        return Some((item, (events, keeper, subscribe, connected)));
+      }
+    },
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+
+  fn prices(levels: &[OrderBookEntry]) -> Vec<String> {
+    levels.iter().map(|level| level.price.to_string()).collect()
+  }
+
+  fn update(book: &mut LocalBook, side: Side, price: &str, quantity: i64) {
+    book.update(side, price.parse().unwrap(), quantity.into());
+  }
+
+  #[test]
+  fn updates() {
+    let mut book = LocalBook::default();
+    update(&mut book, Side::Buy, "0.70", 10);
+    update(&mut book, Side::Buy, "0.72", 5);
+    update(&mut book, Side::Buy, "0.71", 7);
+    update(&mut book, Side::Sell, "0.75", 3);
+    update(&mut book, Side::Sell, "0.73", 1);
+    assert_eq!(prices(book.bids()), vec!["0.72", "0.71", "0.70"]);
+    assert_eq!(prices(book.asks()), vec!["0.73", "0.75"]);
+
+    update(&mut book, Side::Buy, "0.710", 2);
+    assert_eq!(book.bids()[1].quantity, 2.into());
+
+    update(&mut book, Side::Buy, "0.72", 0);
+    update(&mut book, Side::Sell, "0.74", 0);
+    assert_eq!(prices(book.bids()), vec!["0.71", "0.70"]);
+    assert_eq!(prices(book.asks()), vec!["0.73", "0.75"]);
+    assert!(!book.is_crossed());
+
+    update(&mut book, Side::Buy, "0.73", 1);
+    assert!(book.is_crossed());
+  }
+
+  /// Yields an empty book per message, and a gap for `"gap"`.
+  #[derive(Default)]
+  struct Keeper;
+
+  impl BookKeeper for Keeper {
+    type Message = &'static str;
+
+    fn reset(&mut self) {}
+
+    fn apply(&mut self, message: &'static str) -> Result<Applied> {
+      Ok(match message {
+        "gap" => Applied::Gap,
+        _ => Applied::Updated(LocalBook::default().to_order_book(Utc::now())),
+      })
+    }
+  }
+
+  #[tokio::test]
+  async fn resubscribes_on_gap() {
+    let subscriptions = AtomicUsize::new(0);
+    let subscribe = || {
+      let message = match subscriptions.fetch_add(1, Ordering::Relaxed) {
+        0 => "gap",
+        _ => "snapshot",
+      };
+      stream::iter(vec![Ok(Event::Connected), Ok(Event::Message(message))])
+    };
+    let events: Vec<_> = order_book::<Keeper, _, _>(subscribe)
+      .map(|event| match event.unwrap() {
+        Event::Message(_) => "book",
+        Event::Connected => "connected",
+        Event::Reconnected => "reconnected",
+      })
+      .collect()
+      .await;
+    assert_eq!(events, ["connected", "reconnected", "book"]);
+  }
+}
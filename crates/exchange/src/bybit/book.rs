@@ -0,0 +1,143 @@
+use super::{timestamp, OrderBookResponse, PriceVolumePair};
+use crate::{
+  book::{Applied, BookKeeper, LocalBook},
+  error::Result,
+  order::Side,
+};
+
+/// Levels per side of an `orderbook.{depth}.{symbol}` subscription, as offered on spot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Depth {
+  One = 1,
+  #[default]
+  Fifty = 50,
+  TwoHundred = 200,
+}
+
+impl Depth {
+  pub fn topic(self, symbol: &str) -> String {
+    format!("orderbook.{}.{}", self as u16, symbol)
+  }
+}
+
+/// Applies `snapshot` and `delta` messages to a [`LocalBook`], checking that update ids
+/// are contiguous.
+#[derive(Debug, Default)]
+pub(super) struct OrderBookKeeper {
+  book: LocalBook,
+  update_id: Option<u64>,
+  sequence: Option<u64>,
+}
+
+impl BookKeeper for OrderBookKeeper {
+  type Message = OrderBookResponse;
+
+  fn reset(&mut self) {
+    self.book.clear();
+    self.update_id = None;
+    self.sequence = None;
+  }
+
+  fn apply(&mut self, response: OrderBookResponse) -> Result<Applied> {
+    let time = timestamp(response.ts)?;
+
+    match response.t.as_str() {
+      "snapshot" => self.reset(),
+      _ => match self.update_id {
+        None => return Ok(Applied::Skipped),
+        Some(update_id) if response.data.u != update_id + 1 => {
+          tracing::warn!(
+            "{} expected update {} but got {}",
+            response.topic,
+            update_id + 1,
+            response.data.u
+          );
+          return Ok(Applied::Gap);
+        }
+        Some(_) => {}
+      },
+    }
+    if let (Some(previous), Some(sequence)) = (self.sequence, response.data.seq) {
+      if sequence < previous {
+        tracing::warn!("{} sequence went back to {}", response.topic, sequence);
+        return Ok(Applied::Gap);
+      }
+    }
+
+    for &PriceVolumePair(price, quantity) in &response.data.b {
+      self.book.update(Side::Buy, price, quantity);
+    }
+    for &PriceVolumePair(price, quantity) in &response.data.a {
+      self.book.update(Side::Sell, price, quantity);
+    }
+    self.update_id = Some(response.data.u);
+    self.sequence = response.data.seq.or(self.sequence);
+
+    if self.book.is_crossed() {
+      tracing::warn!(
+        "{} crossed after update {}",
+        response.topic,
+        response.data.u
+      );
+      return Ok(Applied::Gap);
+    }
+    Ok(Applied::Updated(self.book.to_order_book(time)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{error::decode, order::OrderBook};
+
+  fn message(t: &str, u: u64, b: &str, a: &str) -> OrderBookResponse {
+    decode(&format!(
+      r#"{{"topic":"orderbook.50.MATICUSDT","ts":1703024689167,"type":"{}","data":{{"s":"MATICUSDT","b":{},"a":{},"u":{},"seq":{}}}}}"#,
+      t, b, a, u, u
+    ))
+    .unwrap()
+  }
+
+  fn book(applied: Applied) -> OrderBook {
+    match applied {
+      Applied::Updated(order_book) => order_book,
+      applied => panic!("expected a book, got {:?}", applied),
+    }
+  }
+
+  #[test]
+  fn snapshot_and_deltas() {
+    let mut keeper = OrderBookKeeper::default();
+    assert!(matches!(
+      keeper.apply(message("delta", 7, "[]", "[]")).unwrap(),
+      Applied::Skipped
+    ));
+
+    let snapshot = message(
+      "snapshot",
+      10,
+      r#"[["0.70","100"],["0.69","50"]]"#,
+      r#"[["0.71","80"]]"#,
+    );
+    let order_book = book(keeper.apply(snapshot).unwrap());
+    assert_eq!(order_book.bids.len(), 2);
+    assert_eq!(order_book.asks[0].price, "0.71".parse().unwrap());
+
+    let delta = message("delta", 11, r#"[["0.70","0"]]"#, r#"[["0.72","5"]]"#);
+    let order_book = book(keeper.apply(delta).unwrap());
+    assert_eq!(order_book.bids[0].price, "0.69".parse().unwrap());
+    assert_eq!(order_book.asks.len(), 2);
+  }
+
+  #[test]
+  fn gap() {
+    let mut keeper = OrderBookKeeper::default();
+    keeper
+      .apply(message("snapshot", 10, r#"[["0.70","100"]]"#, "[]"))
+      .unwrap();
+    assert!(matches!(
+      keeper.apply(message("delta", 12, "[]", "[]")).unwrap(),
+      Applied::Gap
+    ));
+  }
+}
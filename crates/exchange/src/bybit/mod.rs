@@ -1,54 +1,232 @@
-use std::{collections::HashMap, env, pin::Pin, str::FromStr};
-
-use chrono::Utc;
-use futures::{stream, SinkExt, Stream, StreamExt};
-use hex::encode;
-use hmac::{Hmac, Mac};
-use reqwest::{header::HeaderMap, Response};
-use serde::{Deserialize, Serialize};
-use sha2::Sha256;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use url::Url;
+mod book;
+
+pub use book::Depth;
+
+use std::{
+  collections::HashMap,
+  str::FromStr,
+  sync::{Arc, Mutex, RwLock},
+  time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::{Response, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
 use uuid::Uuid;
 
-use crate::pair::Pair;
+use crate::{
+  balance::{self, Balance, Balances},
+  batch::in_grouped_batches,
+  book::order_book,
+  decimal::{Decimal, Price, Quantity},
+  environment::{EnvConfig, Environment},
+  error::{decode, Error, ErrorKind, Result},
+  ids::{self, client_order_id, SharedOrderIds},
+  instrument::{Instrument, Market},
+  order::{
+    Cancel, Fill, OrderAck, OrderBook, OrderRef, OrderState, OrderType, OrderUpdate, Request, Side,
+    TimeInForce, Trade,
+  },
+  pair::{self, Pair, Pairs},
+  position::{self, Position, Positions},
+  rate_limit::BybitLimits,
+  rest::{HttpConfig, RestClient},
+  sign::{BybitSigner, Signer},
+  traits::{
+    cancel_and_submit, BalancesManager, BoxStream, DeadManSwitch, FromApi, OrderManager,
+    PairGenerator, PositionManager, StreamFactory,
+  },
+  ws::{flat_messages, until_refused, Event, Protocol, Session},
+};
 
 pub struct Bybit {
-  api_url: String,
+  rest: RestClient<BybitSigner>,
+  /// Host of the public streams, each category has its own path.
   public_wss_url: String,
   private_wss_url: String,
-  api_key: String,
-  secret_key: String,
-  to_internal_ids: HashMap<String, String>,
-  from_internal_ids: HashMap<String, String>,
-  pairs: HashMap<(String, String), Pair>,
-  balances: HashMap<String, f64>,
+  book_depth: Depth,
+  ids: SharedOrderIds,
+  pairs: Pairs,
+  balances: Balances,
+  positions: Positions,
+  /// Product lines whose instruments are loaded, the first listed wins a symbol traded on
+  /// several.
+  categories: Vec<Category>,
+  /// The private session subscribed to the `dcp` topics of `categories` while the
+  /// [`DeadManSwitch`] is armed.
+  dcp: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// Bybit's product lines, each with its own order book and rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+  Spot,
+  Linear,
+  Inverse,
+}
+impl Category {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Self::Spot => "spot",
+      Self::Linear => "linear",
+      Self::Inverse => "inverse",
+    }
+  }
+
+  /// The disconnect cancel protection product covering the category, and its topic.
+  fn dcp(self) -> (&'static str, &'static str) {
+    match self {
+      Self::Spot => ("SPOT", "dcp.spot"),
+      Self::Linear | Self::Inverse => ("DERIVATIVES", "dcp.future"),
+    }
+  }
+}
+impl From<Market> for Category {
+  fn from(market: Market) -> Self {
+    match market {
+      Market::Spot => Self::Spot,
+      Market::Linear => Self::Linear,
+      Market::Inverse => Self::Inverse,
+    }
+  }
+}
+impl From<Category> for Market {
+  fn from(category: Category) -> Self {
+    match category {
+      Category::Spot => Self::Spot,
+      Category::Linear => Self::Linear,
+      Category::Inverse => Self::Inverse,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BybitOrderType {
+  Limit,
+  Market,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BybitTimeInForce {
+  #[serde(rename = "GTC")]
+  GoodTillCancel,
+  #[serde(rename = "IOC")]
+  ImmediateOrCancel,
+  #[serde(rename = "FOK")]
+  FillOrKill,
+  PostOnly,
+}
+
+/// Separates spot stop orders, which rest until triggered, from plain ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum OrderFilter {
+  Order,
+  StopOrder,
+}
+
+/// What the quantity of a spot market order counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MarketUnit {
+  BaseCoin,
+  QuoteCoin,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SubmitRequest {
-  category: String,
+  /// Left out inside batches, which carry the category once.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  category: Option<Category>,
+  order_link_id: String,
   symbol: String,
-  side: String,
-  order_type: String,
+  side: Side,
+  order_type: BybitOrderType,
   qty: String,
-  price: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  price: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  trigger_price: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  order_filter: Option<OrderFilter>,
+  /// Derivatives stop orders trigger when the price rises to `trigger_price` (1) or falls to
+  /// it (2).
+  #[serde(skip_serializing_if = "Option::is_none")]
+  trigger_direction: Option<u8>,
+  time_in_force: BybitTimeInForce,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  market_unit: Option<MarketUnit>,
+  /// Derivatives only, spot orders are rejected with it.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  reduce_only: bool,
+  /// Derivatives only, spot orders are rejected with it.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  close_on_trigger: bool,
 }
 impl SubmitRequest {
   pub fn new(
+    category: Category,
     symbol: impl Into<String>,
-    side: impl Into<String>,
+    side: Side,
     qty: impl Into<String>,
     price: impl Into<String>,
   ) -> Self {
     Self {
-      category: "spot".to_string(),
+      category: Some(category),
+      order_link_id: client_order_id(),
       symbol: symbol.into(),
-      side: side.into(),
-      order_type: "Limit".to_string(),
+      side,
+      order_type: BybitOrderType::Limit,
       qty: qty.into(),
-      price: price.into(),
+      price: Some(price.into()),
+      trigger_price: None,
+      order_filter: None,
+      trigger_direction: None,
+      time_in_force: BybitTimeInForce::GoodTillCancel,
+      market_unit: None,
+      reduce_only: false,
+      close_on_trigger: false,
+    }
+  }
+
+  /// Places `request` on the `category` product line.
+  pub fn for_category(request: &Request, category: Category) -> Self {
+    let spot = category == Category::Spot;
+    let stop = request.order_type.is_stop();
+    Self {
+      category: Some(category),
+      order_link_id: request.client_order_id.clone(),
+      symbol: request.symbol.clone(),
+      side: request.side,
+      // Stop orders are plain orders with a trigger price.
+      order_type: match request.order_type {
+        OrderType::Limit | OrderType::StopLimit => BybitOrderType::Limit,
+        OrderType::Market | OrderType::StopMarket => BybitOrderType::Market,
+      },
+      qty: request.quantity.to_string(),
+      price: request.price.map(|price| price.to_string()),
+      trigger_price: request.trigger_price.map(|price| price.to_string()),
+      order_filter: (spot && stop).then_some(OrderFilter::StopOrder),
+      // A buy stop fires as the price rises through the trigger, a sell stop as it falls.
+      trigger_direction: (!spot && stop).then_some(match request.side {
+        Side::Buy => 1,
+        Side::Sell => 2,
+      }),
+      time_in_force: match request.time_in_force {
+        TimeInForce::GoodTillCancel => BybitTimeInForce::GoodTillCancel,
+        TimeInForce::ImmediateOrCancel => BybitTimeInForce::ImmediateOrCancel,
+        TimeInForce::FillOrKill => BybitTimeInForce::FillOrKill,
+        TimeInForce::PostOnly => BybitTimeInForce::PostOnly,
+      },
+      // Spot market buys are sized in the quote coin unless told otherwise.
+      market_unit: (spot && !request.order_type.has_price()).then_some(MarketUnit::BaseCoin),
+      reduce_only: request.reduce_only,
+      close_on_trigger: request.close_on_trigger,
     }
   }
 }
@@ -57,30 +235,157 @@ impl SubmitRequest {
 #[serde(rename_all = "camelCase")]
 pub struct GetBalancesRequest {
   account_type: String,
-  coin: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  coin: Option<String>,
 }
 impl GetBalancesRequest {
   pub fn new(coin: impl Into<String>) -> Self {
+    Self {
+      coin: Some(coin.into()),
+      ..Self::all()
+    }
+  }
+
+  /// Every coin with a balance.
+  pub fn all() -> Self {
     Self {
       account_type: "UNIFIED".to_string(),
-      coin: coin.into(),
+      coin: None,
+    }
+  }
+}
+
+/// Positions are only kept for USDT settled linear contracts.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionListRequest {
+  category: Category,
+  settle_coin: String,
+}
+impl PositionListRequest {
+  pub fn new() -> Self {
+    Self {
+      category: Category::Linear,
+      settle_coin: "USDT".to_string(),
     }
   }
 }
+impl Default for PositionListRequest {
+  fn default() -> Self {
+    Self::new()
+  }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CancelAllRequest {
-  category: String,
+  category: Category,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  symbol: Option<String>,
+  /// Derivatives need a symbol or settle coin, all of ours settle in USDT.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  settle_coin: Option<String>,
 }
 impl CancelAllRequest {
-  pub fn new() -> Self {
+  pub fn new(category: Category) -> Self {
     Self {
-      category: "spot".to_string(),
+      category,
+      symbol: None,
+      settle_coin: (category != Category::Spot).then(|| "USDT".to_string()),
+    }
+  }
+
+  pub fn for_symbol(category: Category, symbol: impl Into<String>) -> Self {
+    Self {
+      category,
+      symbol: Some(symbol.into()),
+      settle_coin: None,
+    }
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DisconnectedCancelAllRequest {
+  product: String,
+  /// Seconds, between 3 and 300.
+  time_window: u64,
+}
+impl DisconnectedCancelAllRequest {
+  pub fn new(category: Category, time_window: Duration) -> Self {
+    Self {
+      product: category.dcp().0.to_string(),
+      time_window: time_window.as_secs().clamp(3, 300),
     }
   }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstrumentsRequest {
+  category: Category,
+}
+impl InstrumentsRequest {
+  pub fn new(category: Category) -> Self {
+    Self { category }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstrumentsResult {
+  pub category: Category,
+  pub list: Vec<InstrumentInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstrumentInfo {
+  pub symbol: String,
+  pub base_coin: String,
+  pub quote_coin: String,
+  pub status: String,
+  pub lot_size_filter: LotSizeFilter,
+  pub price_filter: PriceFilter,
+}
+
+/// Spot instruments step by `basePrecision` and require `minOrderAmt`, derivatives use
+/// `qtyStep` and `minNotionalValue`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LotSizeFilter {
+  pub base_precision: Option<Quantity>,
+  pub qty_step: Option<Quantity>,
+  pub min_order_qty: Quantity,
+  pub min_order_amt: Option<Decimal>,
+  pub min_notional_value: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceFilter {
+  pub tick_size: Price,
+}
+
+impl InstrumentInfo {
+  pub fn into_pair(self, category: Category) -> Pair {
+    let filter = self.lot_size_filter;
+    let instrument = Instrument {
+      symbol: self.symbol,
+      tick_size: self.price_filter.tick_size,
+      lot_size: filter
+        .qty_step
+        .or(filter.base_precision)
+        .unwrap_or(filter.min_order_qty),
+      min_quantity: filter.min_order_qty,
+      min_notional: filter.min_order_amt.or(filter.min_notional_value),
+      contract_size: Decimal::ONE,
+      market: category.into(),
+    };
+    Pair::new(self.base_coin, self.quote_coin, instrument)
+  }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OrderBookResponse {
   pub topic: String,
@@ -95,16 +400,96 @@ pub struct OrderBookDataResponse {
   pub s: String,
   pub b: Vec<PriceVolumePair>,
   pub a: Vec<PriceVolumePair>,
+  pub u: u64,
+  pub seq: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub struct PriceVolumePair(pub String, pub String);
+pub struct PriceVolumePair(pub Price, pub Quantity);
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TradesResponse {
+  pub topic: String,
+  pub ts: u64,
+  pub data: Vec<TradeData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TradeData {
+  #[serde(rename = "T")]
+  pub t: u64,
+  pub s: String,
+  #[serde(rename = "S")]
+  pub side: Side,
+  pub v: Quantity,
+  pub p: Price,
+  pub i: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsResponse {
+  op: Option<String>,
+  success: Option<bool>,
+  ret_msg: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum WsRequestArg {
+  Str(String),
+  Int(i64),
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 struct WsRequest {
   pub req_id: String,
   pub op: String,
-  pub args: Vec<String>,
+  pub args: Vec<WsRequestArg>,
+}
+impl WsRequest {
+  fn message(op: &str, args: Vec<WsRequestArg>) -> Result<Message> {
+    let request = Self {
+      req_id: Uuid::new_v4().to_string(),
+      op: op.to_string(),
+      args,
+    };
+    let text = serde_json::to_string(&request).map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    Ok(Message::Text(text))
+  }
+}
+
+/// Bybit framing for [`Session`]: `auth` login, `subscribe` and a JSON `ping` every 20s.
+#[derive(Clone)]
+struct BybitProtocol {
+  signer: Option<BybitSigner>,
+}
+
+impl Protocol for BybitProtocol {
+  fn login(&self) -> Result<Option<Message>> {
+    let Some(signer) = &self.signer else {
+      return Ok(None);
+    };
+    let auth = signer.realtime_auth(signer.clock().now_millis());
+    let args = vec![
+      WsRequestArg::Str(auth.api_key),
+      WsRequestArg::Int(auth.expires),
+      WsRequestArg::Str(auth.signature),
+    ];
+    WsRequest::message("auth", args).map(Some)
+  }
+
+  fn subscribe(&self, topics: &[String]) -> Result<Message> {
+    let args = topics.iter().cloned().map(WsRequestArg::Str).collect();
+    WsRequest::message("subscribe", args)
+  }
+
+  fn ping(&self) -> Result<Message> {
+    WsRequest::message("ping", Vec::new())
+  }
+
+  fn ping_interval(&self) -> Duration {
+    Duration::from_secs(20)
+  }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -115,10 +500,40 @@ pub struct ActiveOrdersResponse {
   pub creation_time: u64,
   pub data: Vec<OrderData>,
 }
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionsResponse {
+  pub topic: String,
+  pub id: String,
+  pub creation_time: u64,
+  pub data: Vec<ExecutionData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionData {
+  pub category: String,
+  pub symbol: String,
+  pub exec_id: String,
+  pub exec_type: String,
+  pub order_id: String,
+  pub order_link_id: String,
+  pub side: Side,
+  pub exec_price: Price,
+  pub exec_qty: Quantity,
+  pub exec_fee: Decimal,
+  /// Only sent for spot, where the fee is charged in the asset received.
+  #[serde(default)]
+  pub fee_currency: String,
+  pub exec_time: String,
+  pub is_maker: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderData {
   pub category: String,
+  pub order_id: String,
   pub symbol: String,
   pub order_link_id: String,
   pub block_trade_id: String,
@@ -155,20 +570,104 @@ pub struct OrderData {
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct GetBalancesResponse {
-  pub ret_code: i32,
+pub struct AmendRequest {
+  category: Category,
+  symbol: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  order_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  order_link_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  qty: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  price: Option<String>,
+}
+impl AmendRequest {
+  /// Amends `order_id` to the price and quantity of `request`, by `orderLinkId` if `ours`.
+  pub fn for_order(order_id: &str, ours: bool, request: &Request, category: Category) -> Self {
+    Self {
+      category,
+      symbol: request.symbol.clone(),
+      order_id: (!ours).then(|| order_id.to_string()),
+      order_link_id: ours.then(|| order_id.to_string()),
+      qty: Some(request.quantity.to_string()),
+      price: request.price.map(|price| price.to_string()),
+    }
+  }
+}
+
+/// Orders per `create-batch` and `cancel-batch` call, the spot limit, which the other
+/// categories allow as well.
+const BATCH_SIZE: usize = 10;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchRequest<T> {
+  category: Category,
+  request: Vec<T>,
+}
+impl<T> BatchRequest<T> {
+  pub fn new(category: Category, request: Vec<T>) -> Self {
+    Self { category, request }
+  }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelRequest {
+  symbol: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  order_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  order_link_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResult {
+  pub list: Vec<OrderResult>,
+}
+
+/// Per order outcome of a batch call, in `retExtInfo`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchStatuses {
+  pub list: Vec<BatchStatus>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchStatus {
+  pub code: i64,
+  pub msg: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiStatus {
+  ret_code: i64,
+  ret_msg: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiResponse<T> {
+  pub ret_code: i64,
   pub ret_msg: String,
-  pub result: GetBalancesResult,
+  pub result: T,
   pub ret_ext_info: serde_json::Value,
   pub time: i64,
 }
 
-impl FromStr for GetBalancesResponse {
-  type Err = serde_json::Error;
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderResult {
+  pub order_id: String,
+  pub order_link_id: String,
+}
 
-  fn from_str(s: &str) -> Result<Self, Self::Err> {
-    serde_json::from_str(s)
-  }
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelAllResult {
+  pub list: Vec<OrderResult>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -198,6 +697,56 @@ pub struct GetBalancesAccount {
   pub coin: Vec<GetBalancesCoin>,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerTimeResult {
+  pub time_second: String,
+  pub time_nano: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionListResult {
+  pub list: Vec<PositionData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionsResponse {
+  pub topic: String,
+  pub id: String,
+  pub creation_time: u64,
+  pub data: Vec<PositionData>,
+}
+
+/// A position as listed over REST or pushed on the `position` topic, which calls the average
+/// price `entryPrice`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionData {
+  pub symbol: String,
+  /// `Buy`, `Sell`, or empty while flat.
+  pub side: String,
+  pub size: String,
+  #[serde(alias = "entryPrice")]
+  pub avg_price: String,
+  #[serde(default)]
+  pub mark_price: String,
+  #[serde(default)]
+  pub unrealised_pnl: String,
+  #[serde(default)]
+  pub liq_price: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalletResponse {
+  pub topic: String,
+  pub id: String,
+  pub creation_time: u64,
+  pub data: Vec<GetBalancesAccount>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetBalancesCoin {
@@ -225,226 +774,962 @@ pub struct GetBalancesCoin {
 }
 
 impl Bybit {
-  pub fn new() -> Self {
+  /// Connects to one of Bybit's deployments. Demo trading has no public streams of its
+  /// own, it follows mainnet market data.
+  pub fn from_environment(environment: Environment, api_key: &str, secret_key: &str) -> Self {
+    let (api_url, public_wss_url, private_wss_url) = match environment {
+      Environment::Mainnet => (
+        "https://api.bybit.com",
+        "wss://stream.bybit.com",
+        "wss://stream.bybit.com",
+      ),
+      Environment::Testnet => (
+        "https://api-testnet.bybit.com",
+        "wss://stream-testnet.bybit.com",
+        "wss://stream-testnet.bybit.com",
+      ),
+      Environment::Demo => (
+        "https://api-demo.bybit.com",
+        "wss://stream.bybit.com",
+        "wss://stream-demo.bybit.com",
+      ),
+    };
+    Self::with_urls(
+      api_url,
+      public_wss_url,
+      private_wss_url,
+      api_key,
+      secret_key,
+    )
+  }
+
+  /// Configures the adapter from `BYBIT_API_KEY`, `BYBIT_SECRET_KEY` and optionally
+  /// `BYBIT_ENVIRONMENT` or `BYBIT_API_URL` plus `BYBIT_WSS_URL`, `BYBIT_PROXY` and
+  /// `BYBIT_LOCAL_ADDRESS`.
+  pub fn from_env() -> Result<Self> {
+    let config = EnvConfig::read("BYBIT")?;
+    let adapter = match config.urls {
+      Some((api_url, wss_url)) => {
+        Self::from_api(&api_url, &wss_url, &config.api_key, &config.secret_key)
+      }
+      None => Self::from_environment(config.environment, &config.api_key, &config.secret_key),
+    };
+    adapter.with_http_config(&config.http)
+  }
+
+  /// Stream urls are the host part, the `/v5/...` paths are appended.
+  fn with_urls(
+    api_url: &str,
+    public_wss_url: &str,
+    private_wss_url: &str,
+    api_key: &str,
+    secret_key: &str,
+  ) -> Self {
     Self {
-      api_url: "https://api.bybit.com".to_string(),
-      public_wss_url: "wss://stream.bybit.com/v5/public/spot".to_string(),
-      private_wss_url: "wss://stream.bybit.com/v5/private".to_string(),
-      api_key: env::var("BYBIT_API_KEY").unwrap(),
-      secret_key: env::var("BYBIT_SECRET_KEY").unwrap(),
-      to_internal_ids: HashMap::new(),
-      from_internal_ids: HashMap::new(),
-      pairs: HashMap::new(),
-      balances: HashMap::new(),
+      rest: RestClient::new(api_url, BybitSigner::new(api_key, secret_key))
+        .with_rate_limits(BybitLimits),
+      public_wss_url: public_wss_url.trim_end_matches('/').to_string(),
+      private_wss_url: format!("{}/v5/private", private_wss_url.trim_end_matches('/')),
+      book_depth: Depth::default(),
+      ids: SharedOrderIds::default(),
+      pairs: Arc::new(RwLock::new(HashMap::new())),
+      balances: Balances::default(),
+      positions: Positions::default(),
+      categories: vec![Category::Spot, Category::Linear],
+      dcp: Mutex::new(None),
     }
   }
 
-  pub async fn get_balances(&self, coin: &str) -> Result<GetBalancesResponse, serde_json::Error> {
-    let timestamp = Utc::now().timestamp_millis();
-    let request = GetBalancesRequest::new(coin);
-    let qs = serde_qs::to_string(&request).unwrap();
-
-    let param_str = format!("{}{}{}", timestamp, self.api_key, qs);
-    tracing::info!("param_str: {}", param_str);
+  pub async fn get_balances(&self, request: GetBalancesRequest) -> Result<GetBalancesResult> {
+    let response = self
+      .rest
+      .get("/v5/account/wallet-balance")
+      .query(&request)?
+      .send()
+      .await?;
+    decode_response(response).await
+  }
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(param_str.as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
+  pub async fn get_positions(&self, request: PositionListRequest) -> Result<PositionListResult> {
+    let response = self
+      .rest
+      .get("/v5/position/list")
+      .query(&request)?
+      .send()
+      .await?;
+    decode_response(response).await
+  }
 
-    let mut headers = HeaderMap::new();
-    headers.append("X-BAPI-API-KEY", self.api_key.parse().unwrap());
-    headers.append("X-BAPI-TIMESTAMP", timestamp.to_string().parse().unwrap());
-    headers.append("X-BAPI-SIGN", signature.parse().unwrap());
-    headers.append("Content-Type", "application/json".parse().unwrap());
+  pub async fn get_instruments(&self, request: InstrumentsRequest) -> Result<InstrumentsResult> {
+    let response = self
+      .rest
+      .get("/v5/market/instruments-info")
+      .query(&request)?
+      .send()
+      .await?;
+    decode_response(response).await
+  }
 
-    reqwest::Client::new()
-      .get(format!("{}/v5/account/wallet-balance", self.api_url))
-      .headers(headers)
-      .query(&request)
+  pub async fn submit_request(&self, request: SubmitRequest) -> Result<OrderResult> {
+    let response = self
+      .rest
+      .post("/v5/order/create")
+      .json(&request)?
       .send()
-      .await
-      .unwrap()
-      .text()
-      .await
-      .unwrap()
-      .parse()
+      .await?;
+    decode_response(response).await
   }
 
-  pub async fn submit_request(&self, request: SubmitRequest) -> Response {
-    let timestamp = Utc::now().timestamp_millis();
-    let payload = serde_json::to_string(&request).unwrap();
+  pub async fn amend_order(&self, request: AmendRequest) -> Result<OrderResult> {
+    let response = self
+      .rest
+      .post("/v5/order/amend")
+      .json(&request)?
+      .send()
+      .await?;
+    decode_response(response).await
+  }
 
-    let param_str = format!("{}{}{}", timestamp, self.api_key, payload);
-    tracing::info!("param_str: {}", param_str);
+  /// Places up to [`BATCH_SIZE`] orders of one category in one call, reporting a result per
+  /// order.
+  pub async fn submit_batch(
+    &self,
+    category: Category,
+    requests: Vec<SubmitRequest>,
+  ) -> Result<Vec<Result<OrderResult>>> {
+    let requests = requests
+      .into_iter()
+      .map(|request| SubmitRequest {
+        category: None,
+        ..request
+      })
+      .collect();
+    let response = self
+      .rest
+      .post("/v5/order/create-batch")
+      .json(&BatchRequest::new(category, requests))?
+      .send()
+      .await?;
+    decode_batch_response(response).await
+  }
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(param_str.as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
+  /// Cancels up to [`BATCH_SIZE`] orders of one category in one call, reporting a result
+  /// per order.
+  pub async fn cancel_batch(
+    &self,
+    category: Category,
+    requests: Vec<CancelRequest>,
+  ) -> Result<Vec<Result<OrderResult>>> {
+    let response = self
+      .rest
+      .post("/v5/order/cancel-batch")
+      .json(&BatchRequest::new(category, requests))?
+      .send()
+      .await?;
+    decode_batch_response(response).await
+  }
 
-    let mut headers = HeaderMap::new();
-    headers.append("X-BAPI-API-KEY", self.api_key.parse().unwrap());
-    headers.append("X-BAPI-TIMESTAMP", timestamp.to_string().parse().unwrap());
-    headers.append("X-BAPI-SIGN", signature.parse().unwrap());
-    headers.append("Content-Type", "application/json".parse().unwrap());
+  /// Cancels the orders matched by `request`, returning their ids.
+  pub async fn cancel_all(&self, request: CancelAllRequest) -> Result<Vec<String>> {
+    let response = self
+      .rest
+      .post("/v5/order/cancel-all")
+      .json(&request)?
+      .send()
+      .await?;
+    let result: CancelAllResult = decode_response(response).await?;
+    let ids = self.ids.read().expect("order ids lock poisoned");
+    Ok(
+      result
+        .list
+        .into_iter()
+        .map(|order| match order.order_link_id.as_str() {
+          "" => ids.internal(&order.order_id),
+          order_link_id => order_link_id.to_string(),
+        })
+        .collect(),
+    )
+  }
 
-    reqwest::Client::new()
-      .post(format!("{}/v5/order/create", self.api_url))
-      .headers(headers)
-      .body(payload)
+  /// Sets how long after the last `dcp` subscriber disconnects Bybit cancels every order.
+  pub async fn disconnected_cancel_all(&self, request: DisconnectedCancelAllRequest) -> Result<()> {
+    let response = self
+      .rest
+      .post("/v5/order/disconnected-cancel-all")
+      .json(&request)?
       .send()
-      .await
-      .unwrap()
+      .await?;
+    decode_response::<serde_json::Value>(response).await?;
+    Ok(())
+  }
+
+  /// Sets the receive window of signed calls and logins, see
+  /// [`BybitSigner::with_recv_window`].
+  pub fn with_recv_window(mut self, recv_window: Duration) -> Self {
+    self.rest = self
+      .rest
+      .map_signer(|signer| signer.with_recv_window(recv_window));
+    self
   }
 
-  pub async fn cancel_all_active_orders(&self) -> Response {
-    let timestamp = Utc::now().timestamp_millis();
-    let request = CancelAllRequest::new();
-    let payload = serde_json::to_string(&request).unwrap();
+  /// Measures the offset of Bybit's clock, which signed timestamps are then taken from.
+  /// Replies keep correcting drift of more than a second in between.
+  pub async fn sync_clock(&self) -> Result<()> {
+    let sent = Utc::now();
+    let response = self.rest.get("/v5/market/time").unsigned().send().await?;
+    let received = Utc::now();
+    let result: ServerTimeResult = decode_response(response).await?;
+    let nanos: i64 = result
+      .time_nano
+      .parse()
+      .map_err(|_| decode_error(&result.time_nano, "invalid timestamp"))?;
+    let server_time = DateTime::from_timestamp_nanos(nanos);
+    self
+      .rest
+      .signer()
+      .clock()
+      .observe(server_time, Duration::ZERO, sent, received);
+    Ok(())
+  }
 
-    let param_str = format!("{}{}{}", timestamp, self.api_key, payload);
-    tracing::info!("param_str: {}", param_str);
+  /// Sends REST calls through a client built from `config`.
+  pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+    self.rest = self.rest.with_http_config(config)?;
+    Ok(self)
+  }
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(param_str.as_bytes());
-    let signature = encode(mac.finalize().into_bytes());
+  /// Loads instruments of `categories` only, in order of precedence for symbols traded on
+  /// several.
+  pub fn with_categories(mut self, categories: Vec<Category>) -> Self {
+    self.categories = categories;
+    self
+  }
 
-    let mut headers = HeaderMap::new();
-    headers.append("X-BAPI-API-KEY", self.api_key.parse().unwrap());
-    headers.append("X-BAPI-TIMESTAMP", timestamp.to_string().parse().unwrap());
-    headers.append("X-BAPI-SIGN", signature.parse().unwrap());
-    headers.append("Content-Type", "application/json".parse().unwrap());
+  /// The category orders for `symbol` go to, spot for symbols that were not loaded.
+  fn category(&self, symbol: &str) -> Category {
+    self
+      .get_pair(symbol)
+      .map_or(Category::Spot, |pair| pair.instrument.market.into())
+  }
 
-    reqwest::Client::new()
-      .post(format!("{}/v5/order/cancel-all", self.api_url))
-      .headers(headers)
-      .body(payload)
-      .send()
-      .await
-      .unwrap()
+  fn public_url(&self, symbol: &str) -> String {
+    format!(
+      "{}/v5/public/{}",
+      self.public_wss_url,
+      self.category(symbol).as_str()
+    )
   }
 
-  pub async fn watch_order_book(
-    &self,
-    symbol: &str,
-  ) -> Pin<Box<dyn Stream<Item = Result<OrderBookResponse, String>>>> {
-    let (mut ws, _) = connect_async(Url::parse(self.public_wss_url.as_str()).unwrap())
-      .await
-      .unwrap();
+  /// Depth of the books kept by [`StreamFactory::watch_order_book`].
+  pub fn with_book_depth(mut self, depth: Depth) -> Self {
+    self.book_depth = depth;
+    self
+  }
 
-    let request = WsRequest {
-      req_id: Uuid::new_v4().to_string(),
-      op: "subscribe".to_string(),
-      args: vec![format!("orderbook.1.{}", symbol)],
+  fn private_session(&self, topics: Vec<String>) -> Session<BybitProtocol> {
+    let protocol = BybitProtocol {
+      signer: Some(self.rest.signer().clone()),
     };
+    Session::new(self.private_wss_url.clone(), protocol, topics)
+  }
+}
 
-    ws.send(Message::Text(serde_json::to_string(&request).unwrap()))
-      .await
-      .unwrap();
-
-    stream::unfold(ws, |mut ws| async {
-      match ws.next().await {
-        Some(Ok(Message::Text(text))) => {
-          tracing::info!("text: {}", text);
-          let x: OrderBookResponse = match serde_json::from_str(&text) {
-            Ok(x) => x,
-            Err(e) => {
-              tracing::error!("err: {}", e);
-              return Some((Err(e.to_string()), ws));
-            }
-          };
-          Some((Ok(x), ws))
-        }
-        Some(Ok(Message::Ping(x))) => {
-          tracing::info!("ping: {:?}", x);
-          ws.send(Message::Pong(x)).await.unwrap();
-          None
-        }
-        Some(Err(e)) => {
-          tracing::error!("err: {}", e);
-          Some((Err(e.to_string()), ws))
+#[async_trait]
+impl BalancesManager for Bybit {
+  async fn load_all_balances(&self) -> Result<()> {
+    let result = self.get_balances(GetBalancesRequest::all()).await?;
+    let snapshot = result
+      .list
+      .iter()
+      .flat_map(|account| &account.coin)
+      .map(Balance::try_from)
+      .collect::<Result<Vec<_>>>()?;
+    balance::reset(&self.balances, snapshot);
+    Ok(())
+  }
+
+  fn get_balance(&self, ticker: &str) -> Option<Quantity> {
+    balance::get(&self.balances, ticker)
+  }
+}
+
+#[async_trait]
+impl PositionManager for Bybit {
+  async fn load_positions(&self) -> Result<()> {
+    let result = self.get_positions(PositionListRequest::new()).await?;
+    let snapshot = result
+      .list
+      .iter()
+      .map(Position::try_from)
+      .collect::<Result<Vec<_>>>()?;
+    position::reset(&self.positions, snapshot);
+    Ok(())
+  }
+
+  fn get_position(&self, symbol: &str) -> Option<Position> {
+    position::get(&self.positions, symbol)
+  }
+}
+
+/// Backed by disconnect cancel protection: Bybit cancels every order of a product once no
+/// private session subscribed to its `dcp` topic has been connected for the time window.
+/// Arming sets the window of every configured category and keeps such a session open. Bybit cannot turn the protection off, so disarming closes
+/// the session and the orders are cancelled when the window lapses.
+#[async_trait]
+impl DeadManSwitch for Bybit {
+  async fn arm(&self, timeout: Duration) -> Result<()> {
+    // Linear and inverse share the derivatives product.
+    let mut topics: Vec<String> = Vec::new();
+    for &category in &self.categories {
+      let (_, topic) = category.dcp();
+      if !topics.iter().any(|armed| armed == topic) {
+        self
+          .disconnected_cancel_all(DisconnectedCancelAllRequest::new(category, timeout))
+          .await?;
+        topics.push(topic.to_string());
+      }
+    }
+    let mut dcp = self.dcp.lock().expect("dcp lock poisoned");
+    if dcp.as_ref().is_none_or(JoinHandle::is_finished) {
+      let session = self.private_session(topics);
+      *dcp = Some(tokio::spawn(async move {
+        let mut events = Box::pin(session.into_stream());
+        while let Some(event) = events.next().await {
+          if let Err(e) = event {
+            tracing::warn!("dcp session: {}", e);
+          }
         }
-        Some(x) => {
-          tracing::info!("other: {:?}", x);
-          None
+      }));
+    }
+    Ok(())
+  }
+
+  async fn disarm(&self) -> Result<()> {
+    if let Some(session) = self.dcp.lock().expect("dcp lock poisoned").take() {
+      session.abort();
+    }
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl OrderManager for Bybit {
+  async fn submit_requests(&self, requests: Vec<Request>) -> Vec<Result<OrderAck>> {
+    let check = |request: &Request| {
+      request.validate()?;
+      match self.get_pair(&request.symbol) {
+        Some(pair) => pair.instrument.validate(request),
+        None => Market::Spot.validate(request),
+      }
+    };
+    in_grouped_batches(
+      &requests,
+      BATCH_SIZE,
+      |request: &Request| self.category(&request.symbol),
+      check,
+      |batch: Vec<&Request>| async move {
+        let category = self.category(&batch[0].symbol);
+        let results = self
+          .submit_batch(
+            category,
+            batch
+              .iter()
+              .map(|&request| SubmitRequest::for_category(request, category))
+              .collect(),
+          )
+          .await?;
+        let mut ids = self.ids.write().expect("order ids lock poisoned");
+        Ok(
+          batch
+            .iter()
+            .zip(results)
+            .map(|(request, result)| {
+              let result = result?;
+              ids.insert(request.client_order_id.as_str(), result.order_id.as_str());
+              Ok(OrderAck {
+                order_id: request.client_order_id.clone(),
+                exchange_order_id: result.order_id,
+              })
+            })
+            .collect(),
+        )
+      },
+    )
+    .await
+  }
+
+  async fn cancel_orders(&self, cancels: Vec<Cancel>) -> Vec<Result<String>> {
+    in_grouped_batches(
+      &cancels,
+      BATCH_SIZE,
+      |cancel: &Cancel| self.category(&cancel.symbol),
+      |_| Ok(()),
+      |batch: Vec<&Cancel>| async move {
+        let category = self.category(&batch[0].symbol);
+        let requests = {
+          let ids = self.ids.read().expect("order ids lock poisoned");
+          batch
+            .iter()
+            .map(|cancel| {
+              let (order_id, order_link_id) = match ids.locate(&cancel.order) {
+                OrderRef::Client(id) => (None, Some(id)),
+                order => (Some(order.id().to_string()), None),
+              };
+              CancelRequest {
+                symbol: cancel.symbol.clone(),
+                order_id,
+                order_link_id,
+              }
+            })
+            .collect()
+        };
+        let results = self.cancel_batch(category, requests).await?;
+        Ok(
+          batch
+            .iter()
+            .zip(results)
+            .map(|(cancel, result)| result.map(|_| cancel.order_id().to_string()))
+            .collect(),
+        )
+      },
+    )
+    .await
+  }
+
+  async fn cancel_all_active_orders(&self) -> Result<Vec<String>> {
+    let mut cancelled = Vec::new();
+    for &category in &self.categories {
+      cancelled.extend(self.cancel_all(CancelAllRequest::new(category)).await?);
+    }
+    Ok(cancelled)
+  }
+
+  async fn cancel_all_for_symbol(&self, symbol: &str) -> Result<Vec<String>> {
+    let request = CancelAllRequest::for_symbol(self.category(symbol), symbol);
+    self.cancel_all(request).await
+  }
+
+  async fn replace(&self, order_id: &str, request: Request) -> Result<OrderAck> {
+    if !pair::amendable(&self.pairs, order_id, &request) {
+      return cancel_and_submit(self, order_id, request).await;
+    }
+    request.validate()?;
+    if let Some(pair) = self.get_pair(&request.symbol) {
+      pair.instrument.validate(&request)?;
+    }
+    // Orders we placed are known by their `orderLinkId`.
+    let ours = self
+      .ids
+      .read()
+      .expect("order ids lock poisoned")
+      .external(order_id)
+      .is_some();
+    let category = self.category(&request.symbol);
+    let result = self
+      .amend_order(AmendRequest::for_order(order_id, ours, &request, category))
+      .await?;
+    Ok(OrderAck {
+      order_id: order_id.to_string(),
+      exchange_order_id: result.order_id,
+    })
+  }
+}
+
+impl FromApi for Bybit {
+  /// `api_wss_url` serves both the public and the private streams.
+  fn from_api(api_url: &str, api_wss_url: &str, api_key: &str, secret_key: &str) -> Self {
+    Self::with_urls(api_url, api_wss_url, api_wss_url, api_key, secret_key)
+  }
+}
+
+#[async_trait]
+impl PairGenerator for Bybit {
+  async fn load_pairs(&self) -> Result<()> {
+    let mut pairs = HashMap::new();
+    for &category in &self.categories {
+      let instruments = self
+        .get_instruments(InstrumentsRequest::new(category))
+        .await?;
+      for info in instruments.list {
+        pairs
+          .entry(info.symbol.clone())
+          .or_insert_with(|| info.into_pair(category));
+      }
+    }
+    self
+      .pairs
+      .write()
+      .expect("pairs lock poisoned")
+      .extend(pairs);
+    Ok(())
+  }
+
+  fn get_pair(&self, symbol: &str) -> Option<Pair> {
+    self
+      .pairs
+      .read()
+      .expect("pairs lock poisoned")
+      .get(symbol)
+      .cloned()
+  }
+}
+
+impl StreamFactory for Bybit {
+  fn watch_order_book(&self, symbol: &str) -> BoxStream<Event<OrderBook>> {
+    let url = self.public_url(symbol);
+    let topic = self.book_depth.topic(symbol);
+    let subscribe = move || {
+      let session = Session::new(
+        url.clone(),
+        BybitProtocol { signer: None },
+        vec![topic.clone()],
+      );
+      events::<OrderBookResponse>(session).boxed()
+    };
+
+    Box::pin(order_book::<book::OrderBookKeeper, _, _>(subscribe).fuse())
+  }
+
+  fn watch_trades(&self, symbol: &str) -> BoxStream<Event<Trade>> {
+    let session = Session::new(
+      self.public_url(symbol),
+      BybitProtocol { signer: None },
+      vec![format!("publicTrade.{}", symbol)],
+    );
+    let trades = flat_messages(events::<TradesResponse>(session), |response| {
+      response.data.iter().map(Trade::try_from).collect()
+    });
+
+    Box::pin(trades.fuse())
+  }
+
+  fn watch_executions(&self) -> BoxStream<Event<Fill>> {
+    let pairs = self.pairs.clone();
+    let ids = self.ids.clone();
+    // All categories, orders may go to spot or derivatives by symbol.
+    let session = self.private_session(vec!["execution".to_string()]);
+    let fills = flat_messages(events::<ExecutionsResponse>(session), |response| {
+      response
+        .data
+        .iter()
+        // Funding, settlement and the like are not fills of our orders.
+        .filter(|execution| execution.exec_type == "Trade")
+        .map(Fill::try_from)
+        .collect()
+    })
+    .map_ok(move |event| {
+      event.map(|mut fill| {
+        if fill.order_id == fill.exchange_order_id {
+          let ids = ids.read().expect("order ids lock poisoned");
+          fill.order_id = ids.internal(&fill.exchange_order_id);
         }
-        None => {
-          tracing::info!("none");
-          None
+        if fill.fee_currency.is_empty() {
+          let pairs = pairs.read().expect("pairs lock poisoned");
+          if let Some(pair) = pairs.get(&fill.symbol) {
+            // Spot charges the asset received, contracts their settlement asset.
+            fill.fee_currency = match (pair.instrument.market, fill.side) {
+              (Market::Spot, Side::Buy) | (Market::Inverse, _) => pair.base.clone(),
+              _ => pair.quote.clone(),
+            };
+          }
         }
-      }
+        fill
+      })
+    });
+
+    Box::pin(fills.fuse())
+  }
+
+  fn watch_balances(&self) -> BoxStream<Event<Balance>> {
+    let balances = self.balances.clone();
+    let session = self.private_session(vec!["wallet".to_string()]);
+    let updates = flat_messages(events::<WalletResponse>(session), |response| {
+      response
+        .data
+        .iter()
+        .flat_map(|account| &account.coin)
+        .map(Balance::try_from)
+        .collect()
     })
-    .boxed()
+    .map_ok(move |event| {
+      event.map(|balance| {
+        balance::update(&balances, &balance);
+        balance
+      })
+    });
+
+    Box::pin(updates.fuse())
   }
 
-  pub async fn watch_active_orders(
-    &self,
-  ) -> Pin<Box<dyn Stream<Item = Result<ActiveOrdersResponse, String>>>> {
-    let (mut ws, _) = connect_async(Url::parse(self.private_wss_url.as_str()).unwrap())
-      .await
-      .unwrap();
+  fn watch_positions(&self) -> BoxStream<Event<Position>> {
+    let positions = self.positions.clone();
+    let session = self.private_session(vec!["position.linear".to_string()]);
+    let updates = flat_messages(events::<PositionsResponse>(session), |response| {
+      response.data.iter().map(Position::try_from).collect()
+    })
+    .map_ok(move |event| {
+      event.map(|position| {
+        position::update(&positions, &position.symbol, |cached| {
+          *cached = position.clone()
+        });
+        position
+      })
+    });
 
-    let expires = Utc::now().timestamp_millis() + 1000 * 60 * 60 * 24;
+    Box::pin(updates.fuse())
+  }
+
+  fn watch_active_orders(&self) -> BoxStream<Event<OrderUpdate>> {
+    let pairs = self.pairs.clone();
+    let ids = self.ids.clone();
+    let session = self.private_session(vec!["order".to_string()]);
+    let updates = flat_messages(events::<ActiveOrdersResponse>(session), |response| {
+      response.data.iter().map(OrderUpdate::try_from).collect()
+    })
+    .map_ok(move |event| {
+      event.map(|mut update| {
+        ids::resolve(&ids, &mut update);
+        pair::track(&pairs, &update);
+        update
+      })
+    });
 
-    let mut mac = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-    mac.update(format!("GET/realtime{}", expires).as_bytes());
+    Box::pin(updates.fuse())
+  }
+}
 
-    let signature = mac.finalize().into_bytes();
-    let signature_hex = encode(&*signature);
+impl TryFrom<&TradeData> for Trade {
+  type Error = Error;
 
-    tracing::info!("signature_hex: {}", signature_hex);
+  fn try_from(trade: &TradeData) -> Result<Self> {
+    Ok(Self {
+      time: timestamp(trade.t)?,
+      price: trade.p,
+      quantity: trade.v,
+      aggressor_side: trade.side,
+      trade_id: trade.i.clone(),
+    })
+  }
+}
 
-    let auth_request = WsRequest {
-      req_id: Uuid::new_v4().to_string(),
-      op: "authKeyExpires".to_string(),
-      args: vec![self.api_key.clone(), expires.to_string(), signature_hex],
+impl TryFrom<&GetBalancesCoin> for Balance {
+  type Error = Error;
+
+  fn try_from(coin: &GetBalancesCoin) -> Result<Self> {
+    Ok(Self {
+      currency: coin.coin.clone(),
+      quantity: optional(&coin.wallet_balance)?.unwrap_or_default(),
+    })
+  }
+}
+
+impl TryFrom<&PositionData> for Position {
+  type Error = Error;
+
+  fn try_from(position: &PositionData) -> Result<Self> {
+    let size: Quantity = optional(&position.size)?.unwrap_or_default();
+    let unrealised_pnl = match position.unrealised_pnl.as_str() {
+      "" => Decimal::ZERO,
+      value => value
+        .parse()
+        .map_err(|_| decode_error(value, "invalid decimal"))?,
     };
+    Ok(Self {
+      symbol: position.symbol.clone(),
+      size: match position.side.as_str() {
+        "Sell" => -size,
+        _ => size,
+      },
+      entry_price: optional(&position.avg_price)?,
+      mark_price: optional(&position.mark_price)?,
+      unrealised_pnl,
+      liquidation_price: optional(&position.liq_price)?,
+    })
+  }
+}
 
-    ws.send(Message::Text(serde_json::to_string(&auth_request).unwrap()))
-      .await
-      .unwrap();
+impl TryFrom<&ExecutionData> for Fill {
+  type Error = Error;
 
-    let request = WsRequest {
-      req_id: Uuid::new_v4().to_string(),
-      op: "subscribe".to_string(),
-      args: vec!["order.spot".to_string()],
+  fn try_from(execution: &ExecutionData) -> Result<Self> {
+    let time = execution
+      .exec_time
+      .parse()
+      .map_err(|_| decode_error(&execution.exec_time, "invalid timestamp"))?;
+    Ok(Self {
+      order_id: match execution.order_link_id.as_str() {
+        "" => execution.order_id.clone(),
+        order_link_id => order_link_id.to_string(),
+      },
+      exchange_order_id: execution.order_id.clone(),
+      trade_id: execution.exec_id.clone(),
+      symbol: execution.symbol.clone(),
+      side: execution.side,
+      time: timestamp(time)?,
+      price: execution.exec_price,
+      quantity: execution.exec_qty,
+      fee: execution.exec_fee,
+      fee_currency: execution.fee_currency.clone(),
+      is_maker: execution.is_maker,
+    })
+  }
+}
+
+impl TryFrom<&OrderData> for OrderUpdate {
+  type Error = Error;
+
+  fn try_from(order: &OrderData) -> Result<Self> {
+    let state = match order.order_status.as_str() {
+      "Created" => OrderState::PendingNew,
+      "New" | "Untriggered" | "Triggered" | "Active" => OrderState::New,
+      "PartiallyFilled" => OrderState::PartiallyFilled,
+      "Filled" => OrderState::Filled,
+      "Cancelled" | "PartiallyFilledCanceled" | "Deactivated" => OrderState::Cancelled,
+      "Rejected" => OrderState::Rejected,
+      status => return Err(decode_error(status, "unknown order status")),
+    };
+    let side = match order.side.as_str() {
+      "Buy" => Side::Buy,
+      "Sell" => Side::Sell,
+      side => return Err(decode_error(side, "unknown side")),
+    };
+    let order_id = match order.order_link_id.as_str() {
+      "" => order.order_id.clone(),
+      order_link_id => order_link_id.to_string(),
     };
+    Ok(Self {
+      order_id,
+      exchange_order_id: order.order_id.clone(),
+      symbol: Some(order.symbol.clone()),
+      side: Some(side),
+      state: Some(state),
+      price: optional(&order.price)?,
+      quantity: optional(&order.qty)?,
+      filled_quantity: optional(&order.cum_exec_qty)?,
+      average_price: optional(&order.avg_price)?,
+    })
+  }
+}
 
-    ws.send(Message::Text(serde_json::to_string(&request).unwrap()))
-      .await
-      .unwrap();
-
-    stream::unfold(ws, |mut ws| async {
-      match ws.next().await {
-        Some(Ok(Message::Text(text))) => {
-          tracing::info!("text: {}", text);
-          let x: ActiveOrdersResponse = match serde_json::from_str(&text) {
-            Ok(x) => x,
-            Err(e) => {
-              tracing::error!("err: {}", e);
-              return Some((Err("err".to_string()), ws));
-            }
-          };
-          Some((Ok(x), ws))
-        }
-        Some(Ok(Message::Ping(x))) => {
-          tracing::info!("ping: {:?}", x);
-          ws.send(Message::Pong(x)).await.unwrap();
-          Some((Err("pong".to_string()), ws))
-        }
-        Some(Err(e)) => {
-          tracing::error!("err: {}", e);
-          Some((Err("err".to_string()), ws))
-        }
-        Some(x) => {
-          tracing::info!("other: {:?}", x);
-          Some((Err("other".to_string()), ws))
-        }
-        None => {
-          tracing::info!("none");
-          None
-        }
+/// Parses a decimal field, which Bybit quotes as a string and sends as `""` or `"0"` when it
+/// does not apply.
+fn optional<T: FromStr<Err = Error> + Default + PartialEq>(value: &str) -> Result<Option<T>> {
+  match value {
+    "" => Ok(None),
+    value => {
+      let value = value
+        .parse::<T>()
+        .map_err(|_| decode_error(value, "invalid decimal"))?;
+      Ok((value != T::default()).then_some(value))
+    }
+  }
+}
+
+fn decode_error(value: &str, message: &str) -> Error {
+  Error::Decode {
+    source: serde::de::Error::custom(message),
+    payload: value.to_string(),
+  }
+}
+
+fn timestamp(millis: u64) -> Result<DateTime<Utc>> {
+  i64::try_from(millis)
+    .ok()
+    .and_then(DateTime::from_timestamp_millis)
+    .ok_or_else(|| decode_error(&millis.to_string(), "timestamp out of range"))
+}
+
+/// Unwraps the `retCode`/`retMsg` envelope every Bybit REST endpoint replies with.
+async fn decode_response<T: DeserializeOwned>(response: Response) -> Result<T> {
+  decode_api_response(response)
+    .await
+    .map(|response| response.result)
+}
+
+/// Pairs every order of a batch reply with its status from `retExtInfo`.
+async fn decode_batch_response(response: Response) -> Result<Vec<Result<OrderResult>>> {
+  let response: ApiResponse<BatchResult> = decode_api_response(response).await?;
+  let statuses: BatchStatuses =
+    serde_json::from_value(response.ret_ext_info.clone()).map_err(|source| Error::Decode {
+      source,
+      payload: response.ret_ext_info.to_string(),
+    })?;
+  Ok(
+    response
+      .result
+      .list
+      .into_iter()
+      .zip(statuses.list)
+      .map(|(order, status)| match status.code {
+        0 => Ok(order),
+        code => Err(api_error(code, status.msg)),
+      })
+      .collect(),
+  )
+}
+
+/// How long Bybit bans an IP that exceeded its rate limit.
+const IP_BAN: Duration = Duration::from_secs(10 * 60);
+
+async fn decode_api_response<T: DeserializeOwned>(response: Response) -> Result<ApiResponse<T>> {
+  let status = response.status();
+  let text = response.text().await?;
+  tracing::info!("response: {} {}", status, text);
+
+  if status == StatusCode::TOO_MANY_REQUESTS {
+    return Err(Error::RateLimited { retry_after: None });
+  }
+  // Bybit answers 403 to an IP that exceeded its rate limit, which is banned for a while.
+  if status == StatusCode::FORBIDDEN {
+    return Err(Error::RateLimited {
+      retry_after: Some(IP_BAN),
+    });
+  }
+  if status == StatusCode::UNAUTHORIZED {
+    return Err(Error::Auth(format!("{} {}", status, text)));
+  }
+  let api_status: ApiStatus = match decode(&text) {
+    Ok(api_status) => api_status,
+    Err(_) if !status.is_success() => {
+      return Err(Error::Exchange {
+        code: status.as_u16().into(),
+        kind: ErrorKind::from_status(status),
+        message: text,
+      })
+    }
+    Err(e) => return Err(e),
+  };
+  match api_status.ret_code {
+    0 => decode(&text),
+    code => Err(api_error(code, api_status.ret_msg)),
+  }
+}
+
+fn api_error(code: i64, message: String) -> Error {
+  let kind = match code {
+    10006 | 10018 | 10429 => return Error::RateLimited { retry_after: None },
+    10003 | 10004 | 10005 | 10010 | 33004 => return Error::Auth(message),
+    110004 | 110007 | 110012 | 110052 | 170033 | 170131 => ErrorKind::InsufficientBalance,
+    110003 | 170132 | 170133 | 170134 => ErrorKind::InvalidPrice,
+    170135 | 170136 | 170137 | 170140 => ErrorKind::InvalidQuantity,
+    110072 | 170141 => ErrorKind::DuplicateClientId,
+    110001 | 170213 => ErrorKind::OrderNotFound,
+    10002 => ErrorKind::InvalidTimestamp,
+    10000 | 10016 => ErrorKind::SystemBusy,
+    10001 => ErrorKind::InvalidRequest,
+    _ => ErrorKind::Unknown,
+  };
+  Error::Exchange {
+    code,
+    kind,
+    message,
+  }
+}
+
+/// Decodes the data messages of a session, skipping operation acknowledgements.
+fn events<T: DeserializeOwned + Send + 'static>(
+  session: Session<BybitProtocol>,
+) -> impl Stream<Item = Result<Event<T>>> + Send {
+  until_refused(session.into_stream().try_filter_map(|event| async move {
+    match event {
+      Event::Message(text) => decode_ws_message(&text)
+        .transpose()
+        .map(|message| message.map(Event::Message)),
+      Event::Connected => Ok(Some(Event::Connected)),
+      Event::Reconnected => Ok(Some(Event::Reconnected)),
+    }
+  }))
+}
+
+fn decode_ws_message<T: DeserializeOwned>(text: &str) -> Option<Result<T>> {
+  let response: WsResponse = match decode(text) {
+    Ok(response) => response,
+    Err(e) => return Some(Err(e)),
+  };
+  match response.op.as_deref() {
+    None => Some(decode(text)),
+    Some(_) if response.success == Some(false) => {
+      let message = response.ret_msg.unwrap_or_default();
+      match response.op.as_deref() {
+        Some("auth") => Some(Err(Error::Auth(message))),
+        Some("subscribe") => Some(Err(Error::Subscription(message))),
+        _ => Some(Err(Error::exchange(ErrorKind::Unknown, message))),
       }
-    })
-    .boxed()
+    }
+    Some(_) => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn amend_request() {
+    let request = Request::limit("MATICUSDT", Side::Buy, "0.7".parse().unwrap(), 10.into());
+    let amend = AmendRequest::for_order("ours", true, &request, Category::Spot);
+    let amend = serde_json::to_value(amend).unwrap();
+    assert_eq!(amend["orderLinkId"], "ours");
+    assert!(amend.get("orderId").is_none());
+    assert_eq!(amend["price"], "0.7");
+    assert_eq!(amend["qty"], "10");
+
+    let amend = AmendRequest::for_order("b1c9", false, &request, Category::Linear);
+    let amend = serde_json::to_value(amend).unwrap();
+    assert_eq!(amend["category"], "linear");
+    assert_eq!(amend["orderId"], "b1c9");
+    assert!(amend.get("orderLinkId").is_none());
+  }
+
+  #[test]
+  fn submit_request_by_category() {
+    let mut request = Request::limit("MATICUSDT", Side::Sell, "0.7".parse().unwrap(), 10.into());
+    request.order_type = OrderType::StopLimit;
+    request.trigger_price = Some("0.69".parse().unwrap());
+
+    let spot = serde_json::to_value(SubmitRequest::for_category(&request, Category::Spot)).unwrap();
+    assert_eq!(spot["category"], "spot");
+    assert_eq!(spot["orderFilter"], "StopOrder");
+    assert!(spot.get("triggerDirection").is_none());
+
+    let linear =
+      serde_json::to_value(SubmitRequest::for_category(&request, Category::Linear)).unwrap();
+    assert_eq!(linear["category"], "linear");
+    assert_eq!(linear["triggerDirection"], 2);
+    assert!(linear.get("orderFilter").is_none());
+  }
+
+  #[test]
+  fn disconnected_cancel_all_request() {
+    let window = Duration::from_secs(60);
+    let spot = DisconnectedCancelAllRequest::new(Category::Spot, window);
+    let spot = serde_json::to_value(spot).unwrap();
+    assert_eq!(spot["product"], "SPOT");
+    assert_eq!(spot["timeWindow"], 60);
+    let linear = DisconnectedCancelAllRequest::new(Category::Linear, window);
+    assert_eq!(
+      serde_json::to_value(linear).unwrap()["product"],
+      "DERIVATIVES"
+    );
+  }
+
+  #[test]
+  fn api_errors() {
+    let kind = |code: i64| match api_error(code, String::new()) {
+      Error::Exchange { kind, .. } => Some(kind),
+      _ => None,
+    };
+    assert_eq!(kind(170131), Some(ErrorKind::InsufficientBalance));
+    assert_eq!(kind(170134), Some(ErrorKind::InvalidPrice));
+    assert_eq!(kind(170135), Some(ErrorKind::InvalidQuantity));
+    assert_eq!(kind(170141), Some(ErrorKind::DuplicateClientId));
+    assert_eq!(kind(110001), Some(ErrorKind::OrderNotFound));
+    assert_eq!(kind(10002), Some(ErrorKind::InvalidTimestamp));
+    assert_eq!(kind(10016), Some(ErrorKind::SystemBusy));
+    assert_eq!(kind(12345), Some(ErrorKind::Unknown));
+    assert_eq!(kind(10003), None);
+    assert!(api_error(10006, String::new()).is_retryable());
+    assert!(api_error(10016, String::new()).is_retryable());
+    assert!(!api_error(170131, String::new()).is_retryable());
   }
 }
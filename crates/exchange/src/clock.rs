@@ -0,0 +1,71 @@
+use std::{
+  sync::atomic::{AtomicI64, Ordering},
+  time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+/// Estimates how far a venue's clock is ahead of ours, so that signed timestamps land
+/// inside the venue's receive window even when the local clock drifts.
+#[derive(Debug, Default)]
+pub struct Clock {
+  /// Venue time minus local time, in milliseconds.
+  offset: AtomicI64,
+}
+
+impl Clock {
+  /// The venue's current time, in unix milliseconds.
+  pub fn now_millis(&self) -> i64 {
+    Utc::now().timestamp_millis() + self.offset_millis()
+  }
+
+  pub fn offset_millis(&self) -> i64 {
+    self.offset.load(Ordering::Relaxed)
+  }
+
+  /// Learns from a reply that carried `server_time`, truncated to `resolution`, to a
+  /// request sent at `sent` and answered at `received`. The server read its clock somewhere
+  /// in between, so the estimate only replaces the current offset when it disagrees by more
+  /// than that uncertainty.
+  pub fn observe(
+    &self,
+    server_time: DateTime<Utc>,
+    resolution: Duration,
+    sent: DateTime<Utc>,
+    received: DateTime<Utc>,
+  ) {
+    let resolution = i64::try_from(resolution.as_millis()).unwrap_or(i64::MAX);
+    let round_trip = (received - sent).num_milliseconds().max(0);
+    let midpoint = sent.timestamp_millis() + round_trip / 2;
+    let estimate = server_time.timestamp_millis() + resolution / 2 - midpoint;
+    let uncertainty = resolution / 2 + round_trip / 2;
+    if (estimate - self.offset_millis()).abs() > uncertainty {
+      self.offset.store(estimate, Ordering::Relaxed);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn observe() {
+    let clock = Clock::default();
+    let sent = DateTime::from_timestamp_millis(1703024689000).unwrap();
+    let received = DateTime::from_timestamp_millis(1703024689100).unwrap();
+
+    let server_time = DateTime::from_timestamp_millis(1703024691050).unwrap();
+    clock.observe(server_time, Duration::ZERO, sent, received);
+    assert_eq!(clock.offset_millis(), 2000);
+
+    // A one second `Date` header that fits the current offset leaves it alone.
+    let server_time = DateTime::from_timestamp_millis(1703024691000).unwrap();
+    clock.observe(server_time, Duration::from_secs(1), sent, received);
+    assert_eq!(clock.offset_millis(), 2000);
+
+    let server_time = DateTime::from_timestamp_millis(1703024684000).unwrap();
+    clock.observe(server_time, Duration::from_secs(1), sent, received);
+    assert_eq!(clock.offset_millis(), -4550);
+  }
+}
@@ -0,0 +1,196 @@
+use std::{
+  fmt,
+  ops::{Add, Div, Mul, Neg, Sub},
+  str::FromStr,
+};
+
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+pub use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+macro_rules! decimal {
+  ($(#[$meta:meta])* $name:ident) => {
+    $(#[$meta])*
+    #[derive(
+      Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+    )]
+    #[serde(transparent)]
+    pub struct $name(Decimal);
+
+    impl $name {
+      pub const ZERO: Self = Self(Decimal::ZERO);
+
+      pub const fn new(value: Decimal) -> Self {
+        Self(value)
+      }
+
+      pub fn value(self) -> Decimal {
+        self.0
+      }
+
+      pub fn is_zero(self) -> bool {
+        self.0.is_zero()
+      }
+
+      pub fn is_positive(self) -> bool {
+        self.0 > Decimal::ZERO
+      }
+
+      /// Rounds to a multiple of `step`, e.g. an instrument's tick or lot size. A zero
+      /// step leaves the value untouched.
+      pub fn round_to(self, step: Self, strategy: RoundingStrategy) -> Self {
+        if step.is_zero() {
+          return self;
+        }
+        let steps = (self.0 / step.0).round_dp_with_strategy(0, strategy);
+        Self((steps * step.0).normalize())
+      }
+
+      /// Lossy conversion for the maths that has to happen in floating point.
+      pub fn to_f64(self) -> f64 {
+        self.0.to_f64().unwrap_or(f64::NAN)
+      }
+
+      pub fn from_f64(value: f64) -> Result<Self> {
+        Decimal::from_f64(value).map(Self).ok_or_else(|| {
+          Error::InvalidRequest(format!("{} is not a valid {}", value, stringify!($name)))
+        })
+      }
+    }
+
+    impl From<Decimal> for $name {
+      fn from(value: Decimal) -> Self {
+        Self(value)
+      }
+    }
+
+    impl From<i64> for $name {
+      fn from(value: i64) -> Self {
+        Self(value.into())
+      }
+    }
+
+    impl FromStr for $name {
+      type Err = Error;
+
+      fn from_str(value: &str) -> Result<Self> {
+        Decimal::from_str_exact(value)
+          .or_else(|_| Decimal::from_scientific(value))
+          .map(Self)
+          .map_err(|e| Error::InvalidRequest(format!("{:?} is not a valid {}: {}", value, stringify!($name), e)))
+      }
+    }
+
+    impl fmt::Display for $name {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+      }
+    }
+
+    impl Add for $name {
+      type Output = Self;
+
+      fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0)
+      }
+    }
+
+    impl Sub for $name {
+      type Output = Self;
+
+      fn sub(self, other: Self) -> Self {
+        Self(self.0 - other.0)
+      }
+    }
+
+    impl Neg for $name {
+      type Output = Self;
+
+      fn neg(self) -> Self {
+        Self(-self.0)
+      }
+    }
+
+    impl Mul<Decimal> for $name {
+      type Output = Self;
+
+      fn mul(self, factor: Decimal) -> Self {
+        Self(self.0 * factor)
+      }
+    }
+
+    impl Div<Decimal> for $name {
+      type Output = Self;
+
+      fn div(self, divisor: Decimal) -> Self {
+        Self(self.0 / divisor)
+      }
+    }
+  };
+}
+
+decimal! {
+  /// An exact price, serialized as a decimal string so that what we send is what the venue
+  /// reads.
+  Price
+}
+
+decimal! {
+  /// An exact amount of a base asset, quote asset or contracts.
+  Quantity
+}
+
+/// Notional value of `quantity` at `price`.
+impl Mul<Quantity> for Price {
+  type Output = Decimal;
+
+  fn mul(self, quantity: Quantity) -> Decimal {
+    self.0 * quantity.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_and_format() {
+    let price: Price = "0.7000".parse().unwrap();
+    assert_eq!(price.to_string(), "0.7000");
+    assert_eq!(price, "0.7".parse().unwrap());
+    assert!("0.7x".parse::<Price>().is_err());
+    assert_eq!("1e-4".parse::<Price>().unwrap().to_string(), "0.0001");
+
+    let price: Price = serde_json::from_str(r#""0.1""#).unwrap();
+    let quantity: Quantity = serde_json::from_str("0.2").unwrap();
+    assert_eq!(serde_json::to_string(&price).unwrap(), r#""0.1""#);
+    assert_eq!(price.value() + quantity.value(), "0.3".parse().unwrap());
+  }
+
+  #[test]
+  fn round_to() {
+    let tick: Price = "0.0005".parse().unwrap();
+    let price: Price = "0.70037".parse().unwrap();
+    assert_eq!(
+      price.round_to(tick, RoundingStrategy::MidpointNearestEven),
+      "0.7005".parse().unwrap()
+    );
+    assert_eq!(
+      price.round_to(tick, RoundingStrategy::ToZero),
+      "0.7".parse().unwrap()
+    );
+
+    let lot: Quantity = "100".parse().unwrap();
+    let quantity: Quantity = "1299.9".parse().unwrap();
+    assert_eq!(
+      quantity.round_to(lot, RoundingStrategy::ToZero).to_string(),
+      "1200"
+    );
+    assert_eq!(
+      quantity.round_to(Quantity::ZERO, RoundingStrategy::ToZero),
+      quantity
+    );
+  }
+}
@@ -0,0 +1,122 @@
+use std::{env, fmt, str::FromStr};
+
+use crate::{
+  error::{Error, Result},
+  rest::HttpConfig,
+};
+
+/// Which deployment of a venue to talk to. Venues without a separate demo deployment use
+/// their testnet for [`Environment::Demo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Environment {
+  #[default]
+  Mainnet,
+  Testnet,
+  Demo,
+}
+
+impl Environment {
+  /// Reads `name`, defaulting to mainnet when it is not set.
+  pub fn from_env(name: &str) -> Result<Self> {
+    match env::var(name) {
+      Ok(value) => value.parse(),
+      Err(_) => Ok(Self::default()),
+    }
+  }
+}
+
+impl FromStr for Environment {
+  type Err = Error;
+
+  fn from_str(value: &str) -> Result<Self> {
+    match value.to_ascii_lowercase().as_str() {
+      "mainnet" => Ok(Self::Mainnet),
+      "testnet" => Ok(Self::Testnet),
+      "demo" => Ok(Self::Demo),
+      _ => Err(Error::InvalidRequest(format!(
+        "unknown environment {:?}, expected mainnet, testnet or demo",
+        value
+      ))),
+    }
+  }
+}
+
+impl fmt::Display for Environment {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Mainnet => f.write_str("mainnet"),
+      Self::Testnet => f.write_str("testnet"),
+      Self::Demo => f.write_str("demo"),
+    }
+  }
+}
+
+/// Connection settings read from `{prefix}_*` variables: `{prefix}_API_KEY` and
+/// `{prefix}_SECRET_KEY` are required, `{prefix}_API_URL` together with `{prefix}_WSS_URL`
+/// override the endpoints of `{prefix}_ENVIRONMENT`, and `{prefix}_PROXY` and
+/// `{prefix}_LOCAL_ADDRESS` configure the HTTP client.
+pub(crate) struct EnvConfig {
+  pub api_key: String,
+  pub secret_key: String,
+  pub environment: Environment,
+  pub urls: Option<(String, String)>,
+  pub http: HttpConfig,
+}
+
+impl EnvConfig {
+  pub fn read(prefix: &str) -> Result<Self> {
+    let required =
+      |name: String| env::var(&name).map_err(|_| Error::Auth(format!("{} is not set", name)));
+    let api_key = required(format!("{}_API_KEY", prefix))?;
+    let secret_key = required(format!("{}_SECRET_KEY", prefix))?;
+    let environment = Environment::from_env(&format!("{}_ENVIRONMENT", prefix))?;
+    let urls = match (
+      env::var(format!("{}_API_URL", prefix)),
+      env::var(format!("{}_WSS_URL", prefix)),
+    ) {
+      (Ok(api_url), Ok(wss_url)) => Some((api_url, wss_url)),
+      _ => None,
+    };
+    let local_address = match env::var(format!("{}_LOCAL_ADDRESS", prefix)) {
+      Ok(address) => Some(
+        address
+          .parse()
+          .map_err(|_| Error::InvalidRequest(format!("invalid local address {:?}", address)))?,
+      ),
+      Err(_) => None,
+    };
+    let http = HttpConfig {
+      proxy: env::var(format!("{}_PROXY", prefix)).ok(),
+      local_address,
+      ..HttpConfig::default()
+    };
+    Ok(Self {
+      api_key,
+      secret_key,
+      environment,
+      urls,
+      http,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert_eq!(
+      "Testnet".parse::<Environment>().unwrap(),
+      Environment::Testnet
+    );
+    assert_eq!(
+      Environment::Demo
+        .to_string()
+        .parse::<Environment>()
+        .unwrap(),
+      Environment::Demo
+    );
+    assert!("staging".parse::<Environment>().is_err());
+  }
+}
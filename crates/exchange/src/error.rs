@@ -0,0 +1,130 @@
+use std::{sync::Arc, time::Duration};
+
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use tokio_tungstenite::tungstenite;
+
+use crate::order::OrderState;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+  #[error("transport error: {0}")]
+  Transport(#[from] reqwest::Error),
+  #[error("websocket error: {0}")]
+  WebSocket(#[from] Box<tungstenite::Error>),
+  #[error("failed to decode {payload:?}: {source}")]
+  Decode {
+    source: serde_json::Error,
+    payload: String,
+  },
+  #[error("authentication failed: {0}")]
+  Auth(String),
+  #[error("exchange error {code} ({kind:?}): {message}")]
+  Exchange {
+    code: i64,
+    kind: ErrorKind,
+    message: String,
+  },
+  #[error("rate limited, retry after {retry_after:?}")]
+  RateLimited { retry_after: Option<Duration> },
+  #[error("invalid request: {0}")]
+  InvalidRequest(String),
+  /// The venue refused a websocket subscription, the stream will never deliver.
+  #[error("subscription refused: {0}")]
+  Subscription(String),
+  /// A batch call failed as a whole, every order in it reports the same error.
+  #[error("batch failed: {0}")]
+  Batch(Arc<Error>),
+  #[error("order {order_id} cannot go from {from:?} to {to:?}")]
+  OrderState {
+    order_id: String,
+    from: OrderState,
+    to: OrderState,
+  },
+}
+
+/// What a venue rejection means, decoded from its error code or, where the venue has none,
+/// its message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+  InsufficientBalance,
+  /// Off the tick size or outside the allowed price band.
+  InvalidPrice,
+  /// Off the lot size, or below the minimum quantity or notional.
+  InvalidQuantity,
+  DuplicateClientId,
+  OrderNotFound,
+  /// Our timestamp fell outside the receive window.
+  InvalidTimestamp,
+  /// The venue is overloaded or failed internally, the request may not have been handled.
+  SystemBusy,
+  InvalidRequest,
+  Unknown,
+}
+
+impl ErrorKind {
+  pub fn is_retryable(self) -> bool {
+    matches!(self, Self::InvalidTimestamp | Self::SystemBusy)
+  }
+
+  /// The kind of an HTTP error status that came without anything more specific.
+  pub(crate) fn from_status(status: StatusCode) -> Self {
+    match status {
+      StatusCode::BAD_REQUEST => Self::InvalidRequest,
+      status if status.is_server_error() => Self::SystemBusy,
+      _ => Self::Unknown,
+    }
+  }
+}
+
+impl Error {
+  /// An [`Error::Exchange`] the venue did not give a code for.
+  pub(crate) fn exchange(kind: ErrorKind, message: impl Into<String>) -> Self {
+    Self::Exchange {
+      code: -1,
+      kind,
+      message: message.into(),
+    }
+  }
+
+  /// The kind of a venue rejection, looking through failed batches.
+  pub fn kind(&self) -> Option<ErrorKind> {
+    match self {
+      Self::Exchange { kind, .. } => Some(*kind),
+      Self::Batch(e) => e.kind(),
+      _ => None,
+    }
+  }
+
+  /// Whether sending the same request again may succeed. Orders carry a client order id, so
+  /// retrying one whose fate is unknown cannot place it twice.
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Self::Transport(e) => e.is_timeout() || e.is_connect(),
+      Self::WebSocket(_) | Self::RateLimited { .. } => true,
+      Self::Exchange { kind, .. } => kind.is_retryable(),
+      Self::Batch(e) => e.is_retryable(),
+      Self::Decode { .. }
+      | Self::Auth(_)
+      | Self::InvalidRequest(_)
+      | Self::Subscription(_)
+      | Self::OrderState { .. } => false,
+    }
+  }
+}
+
+impl From<tungstenite::Error> for Error {
+  fn from(error: tungstenite::Error) -> Self {
+    Self::WebSocket(Box::new(error))
+  }
+}
+
+/// Deserializes a venue payload, keeping the raw text around for diagnostics.
+pub(crate) fn decode<T: DeserializeOwned>(payload: &str) -> Result<T> {
+  serde_json::from_str(payload).map_err(|source| Error::Decode {
+    source,
+    payload: payload.to_string(),
+  })
+}
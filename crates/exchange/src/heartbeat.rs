@@ -0,0 +1,111 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+  sync::oneshot,
+  task::JoinHandle,
+  time::{interval, MissedTickBehavior},
+};
+
+use crate::{
+  error::{Error, Result},
+  traits::DeadManSwitch,
+};
+
+/// Keeps a [`DeadManSwitch`] armed from a background task. Each refresh pushes the deadline
+/// back by `timeout`, so orders are cancelled only once the process has stopped refreshing
+/// for that long.
+pub struct Heartbeat {
+  stop: oneshot::Sender<()>,
+  task: JoinHandle<Result<()>>,
+}
+
+impl Heartbeat {
+  /// Arms `switch` and refreshes it every quarter of `timeout`, leaving room for a few
+  /// failed refreshes before the venue fires. A timeout too short to split is rejected.
+  pub async fn start<S: DeadManSwitch + ?Sized + 'static>(
+    switch: Arc<S>,
+    timeout: Duration,
+  ) -> Result<Self> {
+    let period = timeout / 4;
+    if period.is_zero() {
+      return Err(Error::InvalidRequest(format!(
+        "heartbeat timeout {:?} is too short to refresh",
+        timeout
+      )));
+    }
+    switch.arm(timeout).await?;
+    let (stop, mut stopped) = oneshot::channel();
+    let task = tokio::spawn(async move {
+      let mut ticks = interval(period);
+      ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+      ticks.tick().await;
+      loop {
+        tokio::select! {
+          _ = ticks.tick() => {
+            if let Err(e) = switch.arm(timeout).await {
+              tracing::warn!("failed to refresh the dead man's switch: {}", e);
+            }
+          }
+          _ = &mut stopped => break,
+        }
+      }
+      switch.disarm().await
+    });
+    Ok(Self { stop, task })
+  }
+
+  /// Stops refreshing and disarms the switch.
+  pub async fn stop(self) -> Result<()> {
+    let _ = self.stop.send(());
+    self.task.await.expect("heartbeat task panicked")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use async_trait::async_trait;
+
+  use super::*;
+
+  #[derive(Default)]
+  struct Switch {
+    calls: Mutex<Vec<Duration>>,
+  }
+
+  #[async_trait]
+  impl DeadManSwitch for Switch {
+    async fn arm(&self, timeout: Duration) -> Result<()> {
+      self.calls.lock().unwrap().push(timeout);
+      Ok(())
+    }
+
+    async fn disarm(&self) -> Result<()> {
+      self.calls.lock().unwrap().push(Duration::ZERO);
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn refresh_and_disarm() {
+    let switch = Arc::new(Switch::default());
+    let timeout = Duration::from_millis(40);
+    let heartbeat = Heartbeat::start(switch.clone(), timeout).await.unwrap();
+    tokio::time::sleep(timeout).await;
+    heartbeat.stop().await.unwrap();
+
+    let calls = switch.calls.lock().unwrap();
+    assert!(calls.len() >= 3);
+    assert!(calls[..calls.len() - 1].iter().all(|&call| call == timeout));
+    assert_eq!(calls.last(), Some(&Duration::ZERO));
+  }
+
+  #[tokio::test]
+  async fn zero_timeout() {
+    let switch = Arc::new(Switch::default());
+    let result = Heartbeat::start(switch.clone(), Duration::ZERO).await;
+    assert!(matches!(result, Err(Error::InvalidRequest(_))));
+    assert!(switch.calls.lock().unwrap().is_empty());
+  }
+}
@@ -0,0 +1,109 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
+
+use uuid::Uuid;
+
+use crate::order::{OrderRef, OrderUpdate};
+
+/// Ids of an adapter's orders, shared with the streams that learn about new ones.
+pub(crate) type SharedOrderIds = Arc<RwLock<OrderIds>>;
+
+/// A fresh client order id. Venues cap them at 36 characters, a hyphenated UUID fits.
+pub fn client_order_id() -> String {
+  Uuid::new_v4().to_string()
+}
+
+/// Maps our client order ids to the ids the venue assigned and back.
+#[derive(Debug, Default)]
+pub struct OrderIds {
+  to_internal_ids: HashMap<String, String>,
+  from_internal_ids: HashMap<String, String>,
+}
+
+impl OrderIds {
+  pub fn insert(&mut self, internal: impl Into<String>, external: impl Into<String>) {
+    let (internal, external) = (internal.into(), external.into());
+    self
+      .to_internal_ids
+      .insert(external.clone(), internal.clone());
+    self.from_internal_ids.insert(internal, external);
+  }
+
+  /// Our id for `external`, the venue id itself for orders placed elsewhere.
+  pub fn internal(&self, external: &str) -> String {
+    self
+      .to_internal_ids
+      .get(external)
+      .cloned()
+      .unwrap_or_else(|| external.to_string())
+  }
+
+  pub fn external(&self, internal: &str) -> Option<&str> {
+    self.from_internal_ids.get(internal).map(String::as_str)
+  }
+
+  /// Resolves an [`OrderRef::Internal`] to the id the venue knows the order by: the client
+  /// id for orders we placed, the venue id otherwise.
+  pub fn locate(&self, order: &OrderRef) -> OrderRef {
+    match order {
+      OrderRef::Internal(id) if self.external(id).is_some() => OrderRef::Client(id.clone()),
+      OrderRef::Internal(id) => OrderRef::Exchange(id.clone()),
+      order => order.clone(),
+    }
+  }
+
+  pub fn remove(&mut self, internal: &str) {
+    if let Some(external) = self.from_internal_ids.remove(internal) {
+      self.to_internal_ids.remove(&external);
+    }
+  }
+}
+
+/// Rewrites `update` to our id, learning the mapping when the venue echoes a client id and
+/// forgetting it once the order is done.
+pub(crate) fn resolve(ids: &SharedOrderIds, update: &mut OrderUpdate) {
+  let mut ids = ids.write().expect("order ids lock poisoned");
+  if update.order_id != update.exchange_order_id {
+    ids.insert(update.order_id.as_str(), update.exchange_order_id.as_str());
+  } else {
+    update.order_id = ids.internal(&update.exchange_order_id);
+  }
+  if update.state.is_some_and(|state| !state.is_live()) {
+    ids.remove(&update.order_id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn mapping() {
+    let mut ids = OrderIds::default();
+    let internal = client_order_id();
+    assert_eq!(internal.len(), 36);
+
+    ids.insert(internal.as_str(), "b1c9");
+    assert_eq!(ids.internal("b1c9"), internal);
+    assert_eq!(ids.external(&internal), Some("b1c9"));
+    assert_eq!(ids.internal("other"), "other");
+    assert_eq!(
+      ids.locate(&OrderRef::Internal(internal.clone())),
+      OrderRef::Client(internal.clone())
+    );
+    assert_eq!(
+      ids.locate(&OrderRef::Internal("other".to_string())),
+      OrderRef::Exchange("other".to_string())
+    );
+    assert_eq!(
+      ids.locate(&OrderRef::Client("manual".to_string())),
+      OrderRef::Client("manual".to_string())
+    );
+
+    ids.remove(&internal);
+    assert_eq!(ids.external(&internal), None);
+    assert_eq!(ids.internal("b1c9"), "b1c9");
+  }
+}
@@ -0,0 +1,162 @@
+use crate::{
+  decimal::{Decimal, Price, Quantity, RoundingStrategy},
+  error::{Error, Result},
+  order::Request,
+};
+
+/// What an instrument trades, which decides the order options venues accept for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Market {
+  #[default]
+  Spot,
+  /// Contracts margined and settled in the quote asset.
+  Linear,
+  /// Contracts margined and settled in the base asset.
+  Inverse,
+}
+
+impl Market {
+  /// Rejects options that only make sense with a position, i.e. outside spot.
+  pub fn validate(self, request: &Request) -> Result<()> {
+    match self {
+      Market::Spot if request.reduce_only || request.close_on_trigger => {
+        Err(Error::InvalidRequest(format!(
+          "{} is spot, it has no position to reduce or close",
+          request.symbol
+        )))
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+/// Trading rules of a symbol as published by its venue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instrument {
+  pub symbol: String,
+  pub tick_size: Price,
+  /// Step of order quantities, in the venue's quantity unit (coins or contracts).
+  pub lot_size: Quantity,
+  pub min_quantity: Quantity,
+  /// Smallest accepted `price * quantity * contract_size`, if the venue enforces one.
+  pub min_notional: Option<Decimal>,
+  /// Base asset per unit of quantity, 1 for spot.
+  pub contract_size: Decimal,
+  pub market: Market,
+}
+
+impl Instrument {
+  pub fn round_price(&self, price: Price, strategy: RoundingStrategy) -> Price {
+    price.round_to(self.tick_size, strategy)
+  }
+
+  /// Rounds down, so a quantity sized from a balance never exceeds it.
+  pub fn round_quantity(&self, quantity: Quantity) -> Quantity {
+    quantity.round_to(self.lot_size, RoundingStrategy::ToZero)
+  }
+
+  /// Order quantity for `base` of the base asset.
+  pub fn quantity_for(&self, base: Quantity) -> Quantity {
+    if self.contract_size.is_zero() {
+      return self.round_quantity(base);
+    }
+    self.round_quantity(base / self.contract_size)
+  }
+
+  /// Base asset in `quantity` units of the instrument, the inverse of
+  /// [`Instrument::quantity_for`].
+  pub fn base_quantity(&self, quantity: Quantity) -> Quantity {
+    if self.contract_size.is_zero() {
+      return quantity;
+    }
+    quantity * self.contract_size
+  }
+
+  /// Checks `request` against the market, tick, lot and minimum sizes.
+  pub fn validate(&self, request: &Request) -> Result<()> {
+    self.market.validate(request)?;
+    for price in [request.price, request.trigger_price].into_iter().flatten() {
+      if self.round_price(price, RoundingStrategy::ToZero) != price {
+        return Err(Error::InvalidRequest(format!(
+          "{} price {} is not a multiple of {}",
+          self.symbol, price, self.tick_size
+        )));
+      }
+    }
+    if self.round_quantity(request.quantity) != request.quantity {
+      return Err(Error::InvalidRequest(format!(
+        "{} quantity {} is not a multiple of {}",
+        self.symbol, request.quantity, self.lot_size
+      )));
+    }
+    if request.quantity < self.min_quantity {
+      return Err(Error::InvalidRequest(format!(
+        "{} quantity {} is below {}",
+        self.symbol, request.quantity, self.min_quantity
+      )));
+    }
+    if let (Some(price), Some(min_notional)) = (request.price, self.min_notional) {
+      let notional = price * request.quantity * self.contract_size;
+      if notional < min_notional {
+        return Err(Error::InvalidRequest(format!(
+          "{} notional {} is below {}",
+          self.symbol, notional, min_notional
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::order::Side;
+
+  fn instrument() -> Instrument {
+    Instrument {
+      symbol: "MATICUSDT".to_string(),
+      tick_size: "0.0001".parse().unwrap(),
+      lot_size: "1000".parse().unwrap(),
+      min_quantity: "1000".parse().unwrap(),
+      min_notional: Some(1.into()),
+      contract_size: "0.001".parse().unwrap(),
+      market: Market::Linear,
+    }
+  }
+
+  #[test]
+  fn quantity_for() {
+    let instrument = instrument();
+    assert_eq!(
+      instrument.quantity_for("12.3456".parse().unwrap()),
+      12000.into()
+    );
+  }
+
+  #[test]
+  fn validate() {
+    let instrument = instrument();
+    let request = |price: &str, quantity: i64| {
+      Request::limit(
+        "MATICUSDT",
+        Side::Buy,
+        price.parse().unwrap(),
+        quantity.into(),
+      )
+    };
+    assert!(instrument.validate(&request("0.7", 2000)).is_ok());
+    assert!(instrument.validate(&request("0.70005", 2000)).is_err());
+    assert!(instrument.validate(&request("0.7", 2500)).is_err());
+    assert!(instrument.validate(&request("0.7", 0)).is_err());
+    assert!(instrument.validate(&request("0.7", 1000)).is_err());
+
+    let reduce = request("0.7", 2000).with_reduce_only(true);
+    assert!(instrument.validate(&reduce).is_ok());
+    let spot = Instrument {
+      market: Market::Spot,
+      ..instrument
+    };
+    assert!(spot.validate(&reduce).is_err());
+  }
+}
@@ -1,16 +1,176 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::{
+  decimal::{Decimal, Price, Quantity},
+  error::{Error, Result},
+  ids::client_order_id,
+  instrument::Instrument,
+  pair::Pair,
+};
+
+/// Lifecycle of an order as reported by the venue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OrderState {
+  /// Accepted by the venue but not yet on the book.
+  PendingNew,
+  New,
+  PartiallyFilled,
+  Filled,
+  Cancelled,
+  Rejected,
+  Expired,
+}
+
+impl OrderState {
+  /// Whether the order may still trade.
+  pub fn is_live(self) -> bool {
+    matches!(self, Self::PendingNew | Self::New | Self::PartiallyFilled)
+  }
+
+  pub fn can_transition_to(self, next: Self) -> bool {
+    use OrderState::*;
+    match (self, next) {
+      (current, next) if current == next => true,
+      (PendingNew, _) => true,
+      (New, PartiallyFilled | Filled | Cancelled | Expired) => true,
+      (PartiallyFilled, Filled | Cancelled | Expired) => true,
+      _ => false,
+    }
+  }
+}
+
+/// A change to an order from a private stream. Venues may leave out fields that did not
+/// change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderUpdate {
+  /// Our client order id, or the venue id for orders placed elsewhere.
+  pub order_id: String,
+  pub exchange_order_id: String,
+  pub symbol: Option<String>,
+  pub side: Option<Side>,
+  pub state: Option<OrderState>,
+  pub price: Option<Price>,
+  pub quantity: Option<Quantity>,
+  /// Cumulative filled quantity.
+  pub filled_quantity: Option<Quantity>,
+  pub average_price: Option<Price>,
+}
+
+/// An order tracked from its [`OrderUpdate`]s.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Order {
-  pub base_quantity: f64,
-  pub quote_quantity: f64,
+  pub order_id: String,
+  pub exchange_order_id: String,
+  pub symbol: String,
+  pub side: Side,
+  pub state: OrderState,
+  pub price: Option<Price>,
+  pub quantity: Quantity,
+  pub filled_quantity: Quantity,
+  pub average_price: Option<Price>,
 }
 
-#[derive(Debug, Clone)]
+impl Order {
+  /// Starts tracking an order, `None` if `update` lacks the fields to identify it.
+  pub fn from_update(update: &OrderUpdate) -> Option<Self> {
+    Some(Self {
+      order_id: update.order_id.clone(),
+      exchange_order_id: update.exchange_order_id.clone(),
+      symbol: update.symbol.clone()?,
+      side: update.side?,
+      state: update.state.unwrap_or(OrderState::PendingNew),
+      price: update.price,
+      quantity: update.quantity?,
+      filled_quantity: update.filled_quantity.unwrap_or_default(),
+      average_price: update.average_price,
+    })
+  }
+
+  /// Applies `update`, refusing illegal transitions and fills that go backwards so that a
+  /// stale message cannot revive a finished order.
+  pub fn apply(&mut self, update: &OrderUpdate) -> Result<()> {
+    let state = update.state.unwrap_or(self.state);
+    if !self.state.can_transition_to(state) {
+      return Err(Error::OrderState {
+        order_id: self.order_id.clone(),
+        from: self.state,
+        to: state,
+      });
+    }
+    if let Some(filled_quantity) = update.filled_quantity {
+      if filled_quantity < self.filled_quantity {
+        return Err(Error::OrderState {
+          order_id: self.order_id.clone(),
+          from: self.state,
+          to: state,
+        });
+      }
+      self.filled_quantity = filled_quantity;
+    }
+    self.state = state;
+    self.price = update.price.or(self.price);
+    self.quantity = update.quantity.unwrap_or(self.quantity);
+    self.average_price = update.average_price.or(self.average_price);
+    Ok(())
+  }
+
+  pub fn is_live(&self) -> bool {
+    self.state.is_live()
+  }
+
+  pub fn remaining_quantity(&self) -> Quantity {
+    self.quantity - self.filled_quantity
+  }
+}
+
+/// One execution of one of our orders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+  /// Our client order id, or the venue id for orders placed elsewhere.
+  pub order_id: String,
+  pub exchange_order_id: String,
+  pub trade_id: String,
+  pub symbol: String,
+  pub side: Side,
+  pub time: DateTime<Utc>,
+  pub price: Price,
+  /// In the venue's quantity unit, like [`Order::quantity`].
+  pub quantity: Quantity,
+  /// Fee paid in `fee_currency`, negative for rebates.
+  pub fee: Decimal,
+  pub fee_currency: String,
+  pub is_maker: bool,
+}
+
+impl Fill {
+  /// The change to our balances in the base and quote assets of `pair`, with the fee taken
+  /// from the base asset when it is charged in it and from the quote asset otherwise.
+  /// `quantity` is converted from contracts where the venue trades them.
+  pub fn delta(&self, pair: &Pair) -> OrderDelta {
+    let quantity = pair.instrument.base_quantity(self.quantity);
+    let notional = Quantity::from(self.price * quantity);
+    let (mut base_delta, mut quote_delta) = match self.side {
+      Side::Buy => (quantity, -notional),
+      Side::Sell => (-quantity, notional),
+    };
+    let fee = Quantity::from(self.fee);
+    match self.fee_currency == pair.base {
+      true => base_delta = base_delta - fee,
+      false => quote_delta = quote_delta - fee,
+    }
+    OrderDelta {
+      base_delta,
+      quote_delta,
+    }
+  }
+}
+
+/// Change to the base and quote balances caused by a [`Fill`].
+#[derive(Debug, Clone, PartialEq)]
 pub struct OrderDelta {
-  pub base_delta: f64,
-  pub quote_delta: f64,
+  pub base_delta: Quantity,
+  pub quote_delta: Quantity,
 }
 
 #[derive(Debug, Clone)]
@@ -20,14 +180,461 @@ pub struct OrderBook {
   pub asks: Vec<OrderBookEntry>,
 }
 
-#[derive(Debug, Clone)]
+impl OrderBook {
+  /// The book with its levels converted from contracts of `instrument` to the base asset.
+  pub(crate) fn in_base(mut self, instrument: &Instrument) -> Self {
+    for entry in self.bids.iter_mut().chain(self.asks.iter_mut()) {
+      entry.quantity = instrument.base_quantity(entry.quantity);
+    }
+    self
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct OrderBookEntry {
-  pub price: f64,
-  pub quantity: f64,
+  pub price: Price,
+  /// In the base asset, not the venue's contracts, like [`Trade::quantity`].
+  pub quantity: Quantity,
+}
+
+/// A public trade.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+  pub time: DateTime<Utc>,
+  pub price: Price,
+  /// In the base asset, not the venue's contracts, so trades compare across venues.
+  pub quantity: Quantity,
+  /// The side of the order that took liquidity.
+  pub aggressor_side: Side,
+  pub trade_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Side {
+  Buy,
+  Sell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OrderType {
+  Limit,
+  Market,
+  /// A limit order placed once the market reaches the trigger price.
+  StopLimit,
+  /// A market order placed once the market reaches the trigger price.
+  StopMarket,
+}
+
+impl OrderType {
+  pub fn is_stop(self) -> bool {
+    matches!(self, Self::StopLimit | Self::StopMarket)
+  }
+
+  pub fn has_price(self) -> bool {
+    matches!(self, Self::Limit | Self::StopLimit)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TimeInForce {
+  GoodTillCancel,
+  ImmediateOrCancel,
+  FillOrKill,
+  /// Rests on the book like [`TimeInForce::GoodTillCancel`], but is cancelled instead of
+  /// taking liquidity if it would cross the spread.
+  PostOnly,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
-  pub price: f64,
-  pub quantity: f64,
+  /// Sent as the venue's client order id. Generated once per request, so submitting the
+  /// same request twice is rejected by the venue instead of creating a second order.
+  pub client_order_id: String,
+  pub symbol: String,
+  pub side: Side,
+  pub order_type: OrderType,
+  pub time_in_force: TimeInForce,
+  /// Limit price, `None` for market orders.
+  pub price: Option<Price>,
+  /// Price that activates a stop order, `None` for other orders.
+  pub trigger_price: Option<Price>,
+  pub quantity: Quantity,
+  /// Only ever shrinks the position, the venue cuts or cancels the order otherwise.
+  pub reduce_only: bool,
+  /// Closes the position once triggered, cancelling other orders if margin is short.
+  pub close_on_trigger: bool,
+}
+
+impl Request {
+  pub fn limit(symbol: impl Into<String>, side: Side, price: Price, quantity: Quantity) -> Self {
+    Self {
+      client_order_id: client_order_id(),
+      symbol: symbol.into(),
+      side,
+      order_type: OrderType::Limit,
+      time_in_force: TimeInForce::GoodTillCancel,
+      price: Some(price),
+      trigger_price: None,
+      quantity,
+      reduce_only: false,
+      close_on_trigger: false,
+    }
+  }
+
+  pub fn market(symbol: impl Into<String>, side: Side, quantity: Quantity) -> Self {
+    Self {
+      client_order_id: client_order_id(),
+      symbol: symbol.into(),
+      side,
+      order_type: OrderType::Market,
+      time_in_force: TimeInForce::ImmediateOrCancel,
+      price: None,
+      trigger_price: None,
+      quantity,
+      reduce_only: false,
+      close_on_trigger: false,
+    }
+  }
+
+  pub fn stop_limit(
+    symbol: impl Into<String>,
+    side: Side,
+    trigger_price: Price,
+    price: Price,
+    quantity: Quantity,
+  ) -> Self {
+    Self {
+      order_type: OrderType::StopLimit,
+      trigger_price: Some(trigger_price),
+      ..Self::limit(symbol, side, price, quantity)
+    }
+  }
+
+  pub fn stop_market(
+    symbol: impl Into<String>,
+    side: Side,
+    trigger_price: Price,
+    quantity: Quantity,
+  ) -> Self {
+    Self {
+      order_type: OrderType::StopMarket,
+      trigger_price: Some(trigger_price),
+      ..Self::market(symbol, side, quantity)
+    }
+  }
+
+  pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+    self.time_in_force = time_in_force;
+    self
+  }
+
+  /// Makes a limit order [`TimeInForce::PostOnly`], so it never crosses the spread.
+  pub fn post_only(self) -> Self {
+    self.with_time_in_force(TimeInForce::PostOnly)
+  }
+
+  pub fn with_reduce_only(mut self, reduce_only: bool) -> Self {
+    self.reduce_only = reduce_only;
+    self
+  }
+
+  pub fn with_close_on_trigger(mut self, close_on_trigger: bool) -> Self {
+    self.close_on_trigger = close_on_trigger;
+    self
+  }
+
+  /// Rejects requests no venue would accept before they cost a round-trip.
+  pub fn validate(&self) -> Result<()> {
+    if self.symbol.is_empty() {
+      return Err(Error::InvalidRequest("symbol is empty".to_string()));
+    }
+    if self.client_order_id.is_empty() || self.client_order_id.len() > 36 {
+      return Err(Error::InvalidRequest(format!(
+        "client order id must be 1 to 36 characters, got {:?}",
+        self.client_order_id
+      )));
+    }
+    if !self.quantity.is_positive() {
+      return Err(Error::InvalidRequest(format!(
+        "quantity must be positive, got {}",
+        self.quantity
+      )));
+    }
+    if self.time_in_force == TimeInForce::PostOnly && !self.order_type.has_price() {
+      return Err(Error::InvalidRequest(
+        "post-only requires a limit price".to_string(),
+      ));
+    }
+    if self.close_on_trigger && !self.order_type.is_stop() {
+      return Err(Error::InvalidRequest(
+        "close-on-trigger requires a stop order".to_string(),
+      ));
+    }
+    match (self.order_type.is_stop(), self.trigger_price) {
+      (true, None) => {
+        return Err(Error::InvalidRequest(
+          "stop orders require a trigger price".to_string(),
+        ))
+      }
+      (true, Some(price)) if !price.is_positive() => {
+        return Err(Error::InvalidRequest(format!(
+          "trigger price must be positive, got {}",
+          price
+        )))
+      }
+      (false, Some(_)) => {
+        return Err(Error::InvalidRequest(
+          "only stop orders take a trigger price".to_string(),
+        ))
+      }
+      _ => {}
+    }
+    match (self.order_type.has_price(), self.price) {
+      (true, None) => Err(Error::InvalidRequest(
+        "limit orders require a price".to_string(),
+      )),
+      (true, Some(price)) if !price.is_positive() => Err(Error::InvalidRequest(format!(
+        "price must be positive, got {}",
+        price
+      ))),
+      (false, Some(_)) => Err(Error::InvalidRequest(
+        "market orders do not take a price".to_string(),
+      )),
+      _ => Ok(()),
+    }
+  }
+}
+
+/// How a [`Cancel`] addresses its order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OrderRef {
+  /// An id as this crate reports it: our client order id, or the venue id for orders placed
+  /// elsewhere.
+  Internal(String),
+  /// A client order id, whoever placed the order.
+  Client(String),
+  /// The id the venue assigned.
+  Exchange(String),
+}
+
+impl OrderRef {
+  pub fn id(&self) -> &str {
+    match self {
+      Self::Internal(id) | Self::Client(id) | Self::Exchange(id) => id,
+    }
+  }
+}
+
+/// Cancellation of one order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cancel {
+  pub symbol: String,
+  pub order: OrderRef,
+}
+
+impl Cancel {
+  /// Cancels an order by the id this crate reported for it.
+  pub fn new(symbol: impl Into<String>, order_id: impl Into<String>) -> Self {
+    Self {
+      symbol: symbol.into(),
+      order: OrderRef::Internal(order_id.into()),
+    }
+  }
+
+  pub fn by_client_id(symbol: impl Into<String>, client_order_id: impl Into<String>) -> Self {
+    Self {
+      symbol: symbol.into(),
+      order: OrderRef::Client(client_order_id.into()),
+    }
+  }
+
+  pub fn by_exchange_id(symbol: impl Into<String>, exchange_order_id: impl Into<String>) -> Self {
+    Self {
+      symbol: symbol.into(),
+      order: OrderRef::Exchange(exchange_order_id.into()),
+    }
+  }
+
+  /// The id the cancel was addressed with.
+  pub fn order_id(&self) -> &str {
+    self.order.id()
+  }
+}
+
+/// Venue acknowledgement of a submitted [`Request`].
+#[derive(Debug, Clone)]
+pub struct OrderAck {
+  /// The request's client order id.
+  pub order_id: String,
+  pub exchange_order_id: String,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::instrument::Market;
+
+  fn price(value: &str) -> Price {
+    value.parse().unwrap()
+  }
+
+  #[test]
+  fn validate() {
+    assert!(
+      Request::limit("MATICUSDT", Side::Buy, price("0.7"), 10.into())
+        .validate()
+        .is_ok()
+    );
+    assert!(Request::market("MATICUSDT", Side::Sell, 10.into())
+      .validate()
+      .is_ok());
+    assert!(
+      Request::limit("MATICUSDT", Side::Buy, price("0.7"), Quantity::ZERO)
+        .validate()
+        .is_err()
+    );
+    assert!(
+      Request::limit("MATICUSDT", Side::Buy, price("-0.7"), 10.into())
+        .validate()
+        .is_err()
+    );
+
+    let mut request = Request::market("MATICUSDT", Side::Sell, 10.into());
+    request.price = Some(price("0.7"));
+    assert!(request.validate().is_err());
+
+    let mut request = Request::market("MATICUSDT", Side::Sell, 10.into());
+    request.client_order_id = "x".repeat(37);
+    assert!(request.validate().is_err());
+
+    assert!(Request::stop_limit(
+      "MATICUSDT",
+      Side::Sell,
+      price("0.6"),
+      price("0.59"),
+      10.into()
+    )
+    .with_reduce_only(true)
+    .with_close_on_trigger(true)
+    .validate()
+    .is_ok());
+    let mut request = Request::stop_market("MATICUSDT", Side::Sell, price("0.6"), 10.into());
+    request.trigger_price = None;
+    assert!(request.validate().is_err());
+    assert!(Request::market("MATICUSDT", Side::Sell, 10.into())
+      .post_only()
+      .validate()
+      .is_err());
+    assert!(
+      Request::limit("MATICUSDT", Side::Buy, price("0.7"), 10.into())
+        .with_close_on_trigger(true)
+        .validate()
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn lifecycle() {
+    let update = |state: OrderState, filled_quantity: i64| OrderUpdate {
+      order_id: "1".to_string(),
+      exchange_order_id: "b1c9".to_string(),
+      symbol: Some("MATICUSDT".to_string()),
+      side: Some(Side::Buy),
+      state: Some(state),
+      price: Some(price("0.7")),
+      quantity: Some(10.into()),
+      filled_quantity: Some(filled_quantity.into()),
+      average_price: None,
+    };
+    let mut order = Order::from_update(&update(OrderState::New, 0)).unwrap();
+    assert!(order.is_live());
+
+    order
+      .apply(&update(OrderState::PartiallyFilled, 4))
+      .unwrap();
+    assert_eq!(order.remaining_quantity(), 6.into());
+    assert!(order
+      .apply(&update(OrderState::PartiallyFilled, 3))
+      .is_err());
+    assert!(order.apply(&update(OrderState::New, 4)).is_err());
+
+    order.apply(&update(OrderState::Filled, 10)).unwrap();
+    assert!(!order.is_live());
+    assert!(order.apply(&update(OrderState::Cancelled, 10)).is_err());
+    assert_eq!(order.state, OrderState::Filled);
+  }
+
+  #[test]
+  fn fill_delta() {
+    let mut fill = Fill {
+      order_id: "1".to_string(),
+      exchange_order_id: "b1c9".to_string(),
+      trade_id: "t1".to_string(),
+      symbol: "MATICUSDT".to_string(),
+      side: Side::Buy,
+      time: Utc::now(),
+      price: price("0.8"),
+      quantity: 10.into(),
+      fee: "0.01".parse().unwrap(),
+      fee_currency: "MATIC".to_string(),
+      is_maker: true,
+    };
+    let pair = |contract_size: &str| {
+      Pair::new(
+        "MATIC",
+        "USDT",
+        Instrument {
+          symbol: "MATICUSDT".to_string(),
+          tick_size: "0.0001".parse().unwrap(),
+          lot_size: 1.into(),
+          min_quantity: 1.into(),
+          min_notional: None,
+          contract_size: contract_size.parse().unwrap(),
+          market: Market::Spot,
+        },
+      )
+    };
+    let spot = pair("1");
+    let delta = fill.delta(&spot);
+    assert_eq!(delta.base_delta, "9.99".parse().unwrap());
+    assert_eq!(delta.quote_delta, "-8".parse().unwrap());
+
+    fill.side = Side::Sell;
+    fill.fee_currency = "USDT".to_string();
+    let delta = fill.delta(&spot);
+    assert_eq!(delta.base_delta, (-10).into());
+    assert_eq!(delta.quote_delta, "7.99".parse().unwrap());
+
+    // BitMEX MATICUSDT contracts are 0.001 MATIC each.
+    fill.quantity = 10000.into();
+    fill.fee = "0.00012".parse().unwrap();
+    let delta = fill.delta(&pair("0.001"));
+    assert_eq!(delta.base_delta, (-10).into());
+    assert_eq!(delta.quote_delta, "7.99988".parse().unwrap());
+  }
+
+  #[test]
+  fn order_book_in_base() {
+    let level = |quantity: i64| OrderBookEntry {
+      price: price("0.8"),
+      quantity: quantity.into(),
+    };
+    let order_book = OrderBook {
+      time: Utc::now(),
+      bids: vec![level(10000)],
+      asks: vec![level(2000)],
+    };
+    let instrument = Instrument {
+      symbol: "MATICUSDT".to_string(),
+      tick_size: "0.0001".parse().unwrap(),
+      lot_size: 1000.into(),
+      min_quantity: 1000.into(),
+      min_notional: None,
+      contract_size: "0.001".parse().unwrap(),
+      market: Market::Linear,
+    };
+    let order_book = order_book.in_base(&instrument);
+    assert_eq!(order_book.bids[0].quantity, 10.into());
+    assert_eq!(order_book.asks[0].quantity, 2.into());
+  }
 }
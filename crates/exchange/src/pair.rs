@@ -1,33 +1,153 @@
-use std::{collections::HashMap, pin::Pin};
-
-use futures::stream::FusedStream;
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
 
 use crate::{
-  order::{Order, OrderBook, OrderDelta, Request},
-  traits::{OrderManager, StreamFactory},
+  error::Result,
+  instrument::Instrument,
+  order::{Order, OrderType, OrderUpdate, Request},
 };
 
+/// Pairs of an adapter keyed by symbol, shared with the streams that keep them current.
+pub(crate) type Pairs = Arc<RwLock<HashMap<String, Pair>>>;
+
 #[derive(Debug, Clone)]
 pub struct Pair {
   pub base: String,
   pub quote: String,
+  pub instrument: Instrument,
+  /// Live orders by our client order id, or the venue id for orders placed elsewhere.
+  /// Orders are dropped once they are filled, cancelled, rejected or expired.
   pub orders: HashMap<String, Order>,
 }
 
-impl StreamFactory for Pair {
-  fn watch_order_book(&self) -> Pin<Box<dyn FusedStream<Item = OrderBook>>> {
-    unimplemented!("watch_order_book")
+impl Pair {
+  pub fn new(base: impl Into<String>, quote: impl Into<String>, instrument: Instrument) -> Self {
+    Self {
+      base: base.into(),
+      quote: quote.into(),
+      instrument,
+      orders: HashMap::new(),
+    }
+  }
+
+  /// Applies `update` to the order it refers to, starting to track unknown orders.
+  pub fn track(&mut self, update: &OrderUpdate) -> Result<()> {
+    match self.orders.get_mut(&update.order_id) {
+      Some(order) => order.apply(update)?,
+      None => match Order::from_update(update) {
+        Some(order) => {
+          self.orders.insert(order.order_id.clone(), order);
+        }
+        None => {
+          tracing::warn!("cannot track {} from {:?}", update.order_id, update);
+          return Ok(());
+        }
+      },
+    }
+    if !self.orders[&update.order_id].is_live() {
+      self.orders.remove(&update.order_id);
+    }
+    Ok(())
+  }
+
+  pub fn live_orders(&self) -> impl Iterator<Item = &Order> {
+    self.orders.values()
+  }
+}
+
+/// Routes `update` to its pair, looking the order up by id when the venue left out the
+/// symbol. Updates that cannot be applied are logged and dropped.
+pub(crate) fn track(pairs: &Pairs, update: &OrderUpdate) {
+  let mut pairs = pairs.write().expect("pairs lock poisoned");
+  let pair = match &update.symbol {
+    Some(symbol) => pairs.get_mut(symbol),
+    None => pairs
+      .values_mut()
+      .find(|pair| pair.orders.contains_key(&update.order_id)),
+  };
+  match pair.map(|pair| pair.track(update)) {
+    Some(Ok(())) => {}
+    Some(Err(e)) => tracing::warn!("{}", e),
+    None => tracing::debug!("no pair for order update {:?}", update),
   }
-  fn watch_active_orders(&self) -> Pin<Box<dyn FusedStream<Item = OrderDelta>>> {
-    unimplemented!("watch_active_orders")
+}
+
+/// Whether order `order_id` can be amended to `request`. Venues amend price and quantity
+/// of limit orders only, a tracked order on the other side has to be replaced.
+pub(crate) fn amendable(pairs: &Pairs, order_id: &str, request: &Request) -> bool {
+  if request.order_type != OrderType::Limit {
+    return false;
+  }
+  let pairs = pairs.read().expect("pairs lock poisoned");
+  match pairs
+    .get(&request.symbol)
+    .and_then(|pair| pair.orders.get(order_id))
+  {
+    Some(order) => order.side == request.side,
+    None => true,
   }
 }
 
-impl OrderManager for Pair {
-  fn submit_requests(&self, requests: Vec<Request>) {
-    unimplemented!("submit_order")
+/// Forgets every tracked order, e.g. before applying a fresh snapshot of open orders.
+pub(crate) fn clear_orders(pairs: &Pairs) {
+  for pair in pairs.write().expect("pairs lock poisoned").values_mut() {
+    pair.orders.clear();
   }
-  fn cancel_all_active_orders(&self) {
-    unimplemented!("cancel_all_active_orders")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    instrument::Market,
+    order::{OrderState, Side},
+  };
+
+  fn pairs() -> Pairs {
+    let instrument = Instrument {
+      symbol: "MATICUSDT".to_string(),
+      tick_size: "0.0001".parse().unwrap(),
+      lot_size: 1.into(),
+      min_quantity: 1.into(),
+      min_notional: None,
+      contract_size: 1.into(),
+      market: Market::Spot,
+    };
+    let mut pair = Pair::new("MATIC", "USDT", instrument);
+    pair
+      .track(&OrderUpdate {
+        order_id: "1".to_string(),
+        exchange_order_id: "b1c9".to_string(),
+        symbol: Some("MATICUSDT".to_string()),
+        side: Some(Side::Buy),
+        state: Some(OrderState::New),
+        price: Some("0.7".parse().unwrap()),
+        quantity: Some(10.into()),
+        filled_quantity: None,
+        average_price: None,
+      })
+      .unwrap();
+    Arc::new(RwLock::new(HashMap::from([(
+      "MATICUSDT".to_string(),
+      pair,
+    )])))
+  }
+
+  #[test]
+  fn amendable() {
+    let pairs = pairs();
+    let limit = |side: Side| Request::limit("MATICUSDT", side, "0.71".parse().unwrap(), 10.into());
+    assert!(super::amendable(&pairs, "1", &limit(Side::Buy)));
+    // Venues cannot move an order to the other side.
+    assert!(!super::amendable(&pairs, "1", &limit(Side::Sell)));
+    assert!(!super::amendable(
+      &pairs,
+      "1",
+      &Request::market("MATICUSDT", Side::Buy, 10.into())
+    ));
+    // Orders we do not track are left to the venue to judge.
+    assert!(super::amendable(&pairs, "2", &limit(Side::Sell)));
   }
 }
@@ -0,0 +1,72 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
+
+use crate::decimal::{Decimal, Price, Quantity};
+
+/// Derivative positions of an adapter keyed by symbol, shared with the streams that keep
+/// them current.
+pub(crate) type Positions = Arc<RwLock<HashMap<String, Position>>>;
+
+/// A derivatives position in venue units, contracts on BitMEX.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+  pub symbol: String,
+  /// Positive when long, negative when short.
+  pub size: Quantity,
+  /// `None` while flat.
+  pub entry_price: Option<Price>,
+  pub mark_price: Option<Price>,
+  /// In the settlement currency.
+  pub unrealised_pnl: Decimal,
+  pub liquidation_price: Option<Price>,
+}
+
+impl Position {
+  pub fn flat(symbol: impl Into<String>) -> Self {
+    Self {
+      symbol: symbol.into(),
+      size: Quantity::ZERO,
+      entry_price: None,
+      mark_price: None,
+      unrealised_pnl: Decimal::ZERO,
+      liquidation_price: None,
+    }
+  }
+
+  pub fn is_flat(&self) -> bool {
+    self.size.is_zero()
+  }
+}
+
+/// Replaces every cached position with `snapshot`.
+pub(crate) fn reset(positions: &Positions, snapshot: impl IntoIterator<Item = Position>) {
+  let mut positions = positions.write().expect("positions lock poisoned");
+  *positions = snapshot
+    .into_iter()
+    .map(|position| (position.symbol.clone(), position))
+    .collect();
+}
+
+/// Applies `change` to the position on `symbol`, starting from flat, and returns the result.
+pub(crate) fn update(
+  positions: &Positions,
+  symbol: &str,
+  change: impl FnOnce(&mut Position),
+) -> Position {
+  let mut positions = positions.write().expect("positions lock poisoned");
+  let position = positions
+    .entry(symbol.to_string())
+    .or_insert_with(|| Position::flat(symbol));
+  change(position);
+  position.clone()
+}
+
+pub(crate) fn get(positions: &Positions, symbol: &str) -> Option<Position> {
+  positions
+    .read()
+    .expect("positions lock poisoned")
+    .get(symbol)
+    .cloned()
+}
@@ -0,0 +1,376 @@
+use std::{
+  collections::HashMap,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, Method};
+
+use crate::error::{Error, Result};
+
+/// Which lane a call waits in. Cancels may use the reserve of every budget, so they go
+/// ahead of new orders even when those are queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+  Normal,
+  Cancel,
+}
+
+/// A budget of `capacity` calls per `period`, refilled continuously. The last `reserve`
+/// calls are kept for [`Priority::Cancel`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limit {
+  pub bucket: String,
+  pub capacity: u32,
+  pub period: Duration,
+  pub reserve: u32,
+}
+
+impl Limit {
+  pub fn new(bucket: impl Into<String>, capacity: u32, period: Duration) -> Self {
+    Self {
+      bucket: bucket.into(),
+      capacity,
+      period,
+      reserve: 0,
+    }
+  }
+
+  pub fn with_reserve(mut self, reserve: u32) -> Self {
+    self.reserve = reserve;
+    self
+  }
+}
+
+/// What a venue reported about a budget in its reply headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+  pub bucket: String,
+  pub remaining: u32,
+  /// When the budget is full again.
+  pub reset: Option<DateTime<Utc>>,
+}
+
+/// A venue's rate limits.
+pub trait RateLimits: Send + Sync {
+  /// The budgets a call to `method path` draws from.
+  fn limits(&self, method: &Method, path: &str) -> Vec<Limit>;
+  fn priority(&self, method: &Method, path: &str) -> Priority;
+  /// The remaining budgets reported in the headers of a reply to `method path`.
+  fn observe(&self, method: &Method, path: &str, headers: &HeaderMap) -> Vec<Status>;
+}
+
+/// No limits, for venues or tests that do not need them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unlimited;
+
+impl RateLimits for Unlimited {
+  fn limits(&self, _method: &Method, _path: &str) -> Vec<Limit> {
+    Vec::new()
+  }
+
+  fn priority(&self, _method: &Method, _path: &str) -> Priority {
+    Priority::Normal
+  }
+
+  fn observe(&self, _method: &Method, _path: &str, _headers: &HeaderMap) -> Vec<Status> {
+    Vec::new()
+  }
+}
+
+/// Bybit's UID limits, counted per order endpoint, on top of the IP limit shared by every
+/// call. Order endpoints are held to the lower derivatives limit of 10 per second.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BybitLimits;
+
+impl RateLimits for BybitLimits {
+  fn limits(&self, _method: &Method, path: &str) -> Vec<Limit> {
+    let mut limits = vec![Limit::new("ip", 600, Duration::from_secs(5)).with_reserve(50)];
+    match path {
+      path if path.starts_with("/v5/order/") => {
+        limits.push(Limit::new(path, 10, Duration::from_secs(1)))
+      }
+      "/v5/position/list" | "/v5/account/wallet-balance" => {
+        limits.push(Limit::new(path, 50, Duration::from_secs(1)))
+      }
+      _ => {}
+    }
+    limits
+  }
+
+  fn priority(&self, _method: &Method, path: &str) -> Priority {
+    match path.contains("cancel") {
+      true => Priority::Cancel,
+      false => Priority::Normal,
+    }
+  }
+
+  fn observe(&self, _method: &Method, path: &str, headers: &HeaderMap) -> Vec<Status> {
+    let Some(remaining) = header(headers, "x-bapi-limit-status") else {
+      return Vec::new();
+    };
+    let reset =
+      header(headers, "x-bapi-limit-reset-timestamp").and_then(DateTime::from_timestamp_millis);
+    vec![Status {
+      bucket: path.to_string(),
+      remaining: remaining.try_into().unwrap_or_default(),
+      reset,
+    }]
+  }
+}
+
+/// BitMEX's budget of 120 calls a minute, and 10 a second for the order endpoints, which
+/// both refill continuously.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitmexLimits;
+
+impl BitmexLimits {
+  fn is_order(method: &Method, path: &str) -> bool {
+    *method != Method::GET && path.starts_with("/api/v1/order")
+  }
+}
+
+impl RateLimits for BitmexLimits {
+  fn limits(&self, method: &Method, path: &str) -> Vec<Limit> {
+    let mut limits = vec![Limit::new("requests", 120, Duration::from_secs(60)).with_reserve(10)];
+    if Self::is_order(method, path) {
+      limits.push(Limit::new("orders", 10, Duration::from_secs(1)).with_reserve(2));
+    }
+    limits
+  }
+
+  fn priority(&self, method: &Method, path: &str) -> Priority {
+    match *method == Method::DELETE || path.ends_with("/cancelAllAfter") {
+      true => Priority::Cancel,
+      false => Priority::Normal,
+    }
+  }
+
+  fn observe(&self, method: &Method, path: &str, headers: &HeaderMap) -> Vec<Status> {
+    let mut statuses = Vec::new();
+    if let Some(remaining) = header(headers, "x-ratelimit-remaining") {
+      statuses.push(Status {
+        bucket: "requests".to_string(),
+        remaining: remaining.try_into().unwrap_or_default(),
+        reset: header(headers, "x-ratelimit-reset")
+          .and_then(|reset| DateTime::from_timestamp(reset, 0)),
+      });
+    }
+    match header(headers, "x-ratelimit-remaining-1s") {
+      Some(remaining) if Self::is_order(method, path) => statuses.push(Status {
+        bucket: "orders".to_string(),
+        remaining: remaining.try_into().unwrap_or_default(),
+        reset: None,
+      }),
+      _ => {}
+    }
+    statuses
+  }
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<i64> {
+  headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+/// Holds calls back until every budget they draw from has room, so the venue never has to
+/// reject them. Calls that would wait longer than `max_wait` fail with
+/// [`Error::RateLimited`] instead.
+pub struct RateLimiter {
+  limits: Box<dyn RateLimits>,
+  buckets: Mutex<HashMap<String, Bucket>>,
+  max_wait: Duration,
+}
+
+impl RateLimiter {
+  pub fn new(limits: impl RateLimits + 'static) -> Self {
+    Self {
+      limits: Box::new(limits),
+      buckets: Mutex::new(HashMap::new()),
+      max_wait: Duration::from_secs(5),
+    }
+  }
+
+  pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+    self.max_wait = max_wait;
+    self
+  }
+
+  /// Waits for a slot for a call to `method path` and takes it.
+  pub async fn acquire(&self, method: &Method, path: &str) -> Result<()> {
+    let limits = self.limits.limits(method, path);
+    let priority = self.limits.priority(method, path);
+    loop {
+      let wait = self.try_acquire(&limits, priority, Instant::now());
+      if wait.is_zero() {
+        return Ok(());
+      }
+      if wait > self.max_wait {
+        return Err(Error::RateLimited {
+          retry_after: Some(wait),
+        });
+      }
+      tokio::time::sleep(wait).await;
+    }
+  }
+
+  /// Takes a slot from every budget of `limits` if all have one, otherwise returns how long
+  /// to wait before trying again.
+  fn try_acquire(&self, limits: &[Limit], priority: Priority, now: Instant) -> Duration {
+    let mut buckets = self.buckets.lock().expect("rate limit lock poisoned");
+    let wait = limits
+      .iter()
+      .map(|limit| {
+        buckets
+          .entry(limit.bucket.clone())
+          .or_insert_with(|| Bucket::new(limit, now))
+          .wait(limit, priority, now)
+      })
+      .max()
+      .unwrap_or_default();
+    if wait.is_zero() {
+      for limit in limits {
+        if let Some(bucket) = buckets.get_mut(&limit.bucket) {
+          bucket.tokens -= 1.0;
+        }
+      }
+    }
+    wait
+  }
+
+  /// Lowers the budgets to what the venue reported in the headers of a reply to
+  /// `method path`.
+  pub fn observe(&self, method: &Method, path: &str, headers: &HeaderMap) {
+    let statuses = self.limits.observe(method, path, headers);
+    if statuses.is_empty() {
+      return;
+    }
+    let (now, utc_now) = (Instant::now(), Utc::now());
+    let mut buckets = self.buckets.lock().expect("rate limit lock poisoned");
+    for status in statuses {
+      if let Some(bucket) = buckets.get_mut(&status.bucket) {
+        let reset = status
+          .reset
+          .and_then(|reset| (reset - utc_now).to_std().ok())
+          .map(|delay| now + delay);
+        bucket.observe(status.remaining, reset, now);
+      }
+    }
+  }
+}
+
+impl Default for RateLimiter {
+  fn default() -> Self {
+    Self::new(Unlimited)
+  }
+}
+
+#[derive(Debug)]
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+  /// Set when the venue reported the budget exhausted until a known time.
+  blocked_until: Option<Instant>,
+}
+
+impl Bucket {
+  fn new(limit: &Limit, now: Instant) -> Self {
+    Self {
+      tokens: limit.capacity.into(),
+      updated: now,
+      blocked_until: None,
+    }
+  }
+
+  fn refill(&mut self, limit: &Limit, now: Instant) {
+    let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+    let rate = f64::from(limit.capacity) / limit.period.as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate).min(limit.capacity.into());
+    self.updated = now;
+  }
+
+  /// How long until a call in lane `priority` may take a slot, zero if it may now.
+  fn wait(&mut self, limit: &Limit, priority: Priority, now: Instant) -> Duration {
+    self.refill(limit, now);
+    if let Some(blocked_until) = self.blocked_until {
+      match blocked_until > now {
+        true => return blocked_until - now,
+        false => self.blocked_until = None,
+      }
+    }
+    let needed = match priority {
+      Priority::Cancel => 1.0,
+      Priority::Normal => 1.0 + f64::from(limit.reserve),
+    };
+    match self.tokens >= needed {
+      true => Duration::ZERO,
+      false => {
+        let rate = f64::from(limit.capacity) / limit.period.as_secs_f64();
+        Duration::from_secs_f64((needed - self.tokens) / rate)
+      }
+    }
+  }
+
+  fn observe(&mut self, remaining: u32, reset: Option<Instant>, now: Instant) {
+    self.tokens = self.tokens.min(remaining.into());
+    self.updated = now;
+    if remaining == 0 {
+      self.blocked_until = reset;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cancels_use_the_reserve() {
+    let limiter = RateLimiter::new(Unlimited);
+    let limits = [Limit::new("orders", 4, Duration::from_secs(1)).with_reserve(2)];
+    let now = Instant::now();
+    assert!(limiter
+      .try_acquire(&limits, Priority::Normal, now)
+      .is_zero());
+    assert!(limiter
+      .try_acquire(&limits, Priority::Normal, now)
+      .is_zero());
+    assert_eq!(
+      limiter.try_acquire(&limits, Priority::Normal, now),
+      Duration::from_millis(250)
+    );
+    assert!(limiter
+      .try_acquire(&limits, Priority::Cancel, now)
+      .is_zero());
+    assert!(limiter
+      .try_acquire(&limits, Priority::Cancel, now)
+      .is_zero());
+    assert_eq!(
+      limiter.try_acquire(&limits, Priority::Cancel, now),
+      Duration::from_millis(250)
+    );
+    assert!(limiter
+      .try_acquire(&limits, Priority::Cancel, now + Duration::from_millis(250))
+      .is_zero());
+  }
+
+  #[test]
+  fn observe() {
+    let limiter = RateLimiter::new(BitmexLimits);
+    let mut headers = HeaderMap::new();
+    headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+    let reset = Utc::now() + chrono::Duration::seconds(30);
+    headers.insert(
+      "x-ratelimit-reset",
+      reset.timestamp().to_string().parse().unwrap(),
+    );
+
+    let limits = BitmexLimits.limits(&Method::GET, "/api/v1/position");
+    assert!(limiter
+      .try_acquire(&limits, Priority::Normal, Instant::now())
+      .is_zero());
+    limiter.observe(&Method::GET, "/api/v1/position", &headers);
+    let wait = limiter.try_acquire(&limits, Priority::Cancel, Instant::now());
+    assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+  }
+}
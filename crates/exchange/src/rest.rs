@@ -0,0 +1,231 @@
+use std::{net::IpAddr, time::Duration};
+
+use chrono::{DateTime, Utc};
+use reqwest::{
+  header::{HeaderMap, DATE},
+  Client, Method, Proxy, Response,
+};
+use serde::Serialize;
+
+use crate::{
+  error::{Error, Result},
+  rate_limit::{RateLimiter, RateLimits},
+  sign::{Payload, Signer},
+};
+
+/// Settings of the HTTP client an adapter keeps for all of its REST calls. Connections are
+/// pooled and kept alive so that orders do not pay for a TLS handshake.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+  pub connect_timeout: Duration,
+  /// Limit on a whole call, from connecting to reading the last byte of the reply.
+  pub timeout: Duration,
+  /// How long an unused pooled connection is kept.
+  pub pool_idle_timeout: Duration,
+  pub tcp_keepalive: Option<Duration>,
+  pub tcp_nodelay: bool,
+  /// Proxy for every call, e.g. `http://10.0.0.1:3128` or `socks5://...`.
+  pub proxy: Option<String>,
+  /// Local address to connect from, to pick an interface or a whitelisted IP.
+  pub local_address: Option<IpAddr>,
+}
+
+impl HttpConfig {
+  pub fn build(&self) -> Result<Client> {
+    let mut builder = Client::builder()
+      .connect_timeout(self.connect_timeout)
+      .timeout(self.timeout)
+      .pool_idle_timeout(self.pool_idle_timeout)
+      .tcp_keepalive(self.tcp_keepalive)
+      .tcp_nodelay(self.tcp_nodelay)
+      .local_address(self.local_address);
+    if let Some(proxy) = &self.proxy {
+      builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    Ok(builder.build()?)
+  }
+}
+
+impl Default for HttpConfig {
+  fn default() -> Self {
+    Self {
+      connect_timeout: Duration::from_secs(5),
+      timeout: Duration::from_secs(10),
+      pool_idle_timeout: Duration::from_secs(90),
+      tcp_keepalive: Some(Duration::from_secs(30)),
+      tcp_nodelay: true,
+      proxy: None,
+      local_address: None,
+    }
+  }
+}
+
+/// Sends REST calls to a venue, signing every one of them with `S`.
+pub struct RestClient<S> {
+  client: Client,
+  api_url: String,
+  signer: S,
+  limiter: RateLimiter,
+}
+
+impl<S: Signer> RestClient<S> {
+  pub fn new(api_url: impl Into<String>, signer: S) -> Self {
+    Self {
+      client: HttpConfig::default()
+        .build()
+        .expect("the default http client builds"),
+      api_url: api_url.into(),
+      signer,
+      limiter: RateLimiter::default(),
+    }
+  }
+
+  /// Replaces the client with one built from `config`.
+  pub fn with_http_config(mut self, config: &HttpConfig) -> Result<Self> {
+    self.client = config.build()?;
+    Ok(self)
+  }
+
+  /// Holds calls back to stay within `limits`.
+  pub fn with_rate_limits(mut self, limits: impl RateLimits + 'static) -> Self {
+    self.limiter = RateLimiter::new(limits);
+    self
+  }
+
+  pub fn signer(&self) -> &S {
+    &self.signer
+  }
+
+  /// Replaces the signer with `f` applied to it.
+  pub fn map_signer(mut self, f: impl FnOnce(S) -> S) -> Self {
+    self.signer = f(self.signer);
+    self
+  }
+
+  pub fn request(&self, method: Method, path: impl Into<String>) -> SignedRequest<'_, S> {
+    SignedRequest {
+      rest: self,
+      method,
+      path: path.into(),
+      query: String::new(),
+      body: String::new(),
+      signed: true,
+    }
+  }
+
+  pub fn get(&self, path: impl Into<String>) -> SignedRequest<'_, S> {
+    self.request(Method::GET, path)
+  }
+
+  pub fn post(&self, path: impl Into<String>) -> SignedRequest<'_, S> {
+    self.request(Method::POST, path)
+  }
+
+  pub fn put(&self, path: impl Into<String>) -> SignedRequest<'_, S> {
+    self.request(Method::PUT, path)
+  }
+
+  pub fn delete(&self, path: impl Into<String>) -> SignedRequest<'_, S> {
+    self.request(Method::DELETE, path)
+  }
+}
+
+/// A request under construction. The query string and body are serialized up front so
+/// that the bytes that get signed are exactly the bytes that get sent.
+pub struct SignedRequest<'a, S> {
+  rest: &'a RestClient<S>,
+  method: Method,
+  path: String,
+  query: String,
+  body: String,
+  signed: bool,
+}
+
+impl<'a, S: Signer> SignedRequest<'a, S> {
+  /// Sends the call without credentials, for public endpoints that should keep working
+  /// while our clock is off.
+  pub fn unsigned(mut self) -> Self {
+    self.signed = false;
+    self
+  }
+
+  pub fn query<T: Serialize>(mut self, query: &T) -> Result<Self> {
+    self.query = serde_qs::to_string(query).map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    Ok(self)
+  }
+
+  pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Result<Self> {
+    self.body = serde_json::to_string(body).map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    Ok(self)
+  }
+
+  pub async fn send(self) -> Result<Response> {
+    self.rest.limiter.acquire(&self.method, &self.path).await?;
+    let payload = Payload {
+      method: &self.method,
+      path: &self.path,
+      query: &self.query,
+      body: &self.body,
+    };
+    let headers = match self.signed {
+      true => self
+        .rest
+        .signer
+        .headers(payload, self.rest.signer.clock().now_millis())?,
+      false => HeaderMap::new(),
+    };
+
+    let url = match self.query.as_str() {
+      "" => format!("{}{}", self.rest.api_url, self.path),
+      query => format!("{}{}?{}", self.rest.api_url, self.path, query),
+    };
+    tracing::info!("{} {} {}", self.method, url, self.body);
+
+    let mut request = self
+      .rest
+      .client
+      .request(self.method.clone(), url)
+      .headers(headers);
+    if !self.body.is_empty() {
+      request = request.body(self.body);
+    }
+    let sent = Utc::now();
+    let response = request.send().await?;
+    // Every reply carries the server's time to the second, enough to catch gross drift.
+    if let Some(date) = response
+      .headers()
+      .get(DATE)
+      .and_then(|date| date.to_str().ok())
+      .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+    {
+      self.rest.signer.clock().observe(
+        date.with_timezone(&Utc),
+        Duration::from_secs(1),
+        sent,
+        Utc::now(),
+      );
+    }
+    self
+      .rest
+      .limiter
+      .observe(&self.method, &self.path, response.headers());
+    Ok(response)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn http_config() {
+    let mut config = HttpConfig {
+      proxy: Some("http://127.0.0.1:3128".to_string()),
+      local_address: Some("127.0.0.1".parse().unwrap()),
+      ..HttpConfig::default()
+    };
+    assert!(config.build().is_ok());
+    config.proxy = Some("not a proxy".to_string());
+    assert!(matches!(config.build(), Err(Error::Transport(_))));
+  }
+}
@@ -0,0 +1,318 @@
+use std::{sync::Arc, time::Duration};
+
+use hex::encode;
+use hmac::{Hmac, Mac};
+use reqwest::{
+  header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+  Method,
+};
+use sha2::Sha256;
+
+use crate::{
+  clock::Clock,
+  error::{Error, Result},
+};
+
+/// Everything a venue signs over for a single REST call.
+#[derive(Debug, Clone, Copy)]
+pub struct Payload<'a> {
+  pub method: &'a Method,
+  pub path: &'a str,
+  pub query: &'a str,
+  pub body: &'a str,
+}
+
+/// Arguments of an `authKeyExpires` style websocket login.
+#[derive(Debug, Clone)]
+pub struct RealtimeAuth {
+  pub api_key: String,
+  pub expires: i64,
+  pub signature: String,
+}
+
+pub trait Signer: Send + Sync {
+  /// Authentication headers for `payload`, signed at `timestamp` (unix milliseconds).
+  fn headers(&self, payload: Payload<'_>, timestamp: i64) -> Result<HeaderMap>;
+
+  /// Credentials for the private websocket login, signed at `timestamp` (unix milliseconds).
+  fn realtime_auth(&self, timestamp: i64) -> RealtimeAuth;
+
+  /// The venue clock to take timestamps from. Clones of a signer share it.
+  fn clock(&self) -> &Clock;
+}
+
+#[derive(Clone)]
+pub struct BybitSigner {
+  api_key: String,
+  secret_key: String,
+  recv_window: Duration,
+  clock: Arc<Clock>,
+}
+
+impl BybitSigner {
+  pub fn new(api_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+    Self {
+      api_key: api_key.into(),
+      secret_key: secret_key.into(),
+      recv_window: Duration::from_secs(5),
+      clock: Arc::default(),
+    }
+  }
+
+  /// How long after its timestamp Bybit still accepts a request, and a websocket login.
+  /// Defaults to 5 seconds.
+  pub fn with_recv_window(mut self, recv_window: Duration) -> Self {
+    self.recv_window = recv_window;
+    self
+  }
+
+  fn recv_window_millis(&self) -> i64 {
+    self.recv_window.as_millis().try_into().unwrap_or(i64::MAX)
+  }
+}
+
+impl Signer for BybitSigner {
+  fn headers(&self, payload: Payload<'_>, timestamp: i64) -> Result<HeaderMap> {
+    // GET requests sign the query string, everything else the JSON body.
+    let params = match *payload.method {
+      Method::GET => payload.query,
+      _ => payload.body,
+    };
+    let recv_window = self.recv_window_millis();
+    let signature = hmac_sha256(
+      &self.secret_key,
+      &format!("{}{}{}{}", timestamp, self.api_key, recv_window, params),
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.append("X-BAPI-API-KEY", header_value(&self.api_key)?);
+    headers.append("X-BAPI-TIMESTAMP", timestamp.into());
+    headers.append("X-BAPI-RECV-WINDOW", recv_window.into());
+    headers.append("X-BAPI-SIGN", header_value(&signature)?);
+    headers.append(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(headers)
+  }
+
+  fn realtime_auth(&self, timestamp: i64) -> RealtimeAuth {
+    let expires = timestamp + self.recv_window_millis();
+    RealtimeAuth {
+      api_key: self.api_key.clone(),
+      expires,
+      signature: hmac_sha256(&self.secret_key, &format!("GET/realtime{}", expires)),
+    }
+  }
+
+  fn clock(&self) -> &Clock {
+    &self.clock
+  }
+}
+
+#[derive(Clone)]
+pub struct BitmexSigner {
+  api_key: String,
+  secret_key: String,
+  expiry: Duration,
+  clock: Arc<Clock>,
+}
+
+impl BitmexSigner {
+  pub fn new(api_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+    Self {
+      api_key: api_key.into(),
+      secret_key: secret_key.into(),
+      expiry: Duration::from_secs(10),
+      clock: Arc::default(),
+    }
+  }
+
+  /// How long a signature stays valid, which bounds how long a captured request can be
+  /// replayed. Defaults to 10 seconds.
+  pub fn with_expiry(mut self, expiry: Duration) -> Self {
+    self.expiry = expiry;
+    self
+  }
+
+  fn expires(&self, timestamp: i64) -> i64 {
+    let expiry: i64 = self.expiry.as_secs().try_into().unwrap_or(i64::MAX);
+    timestamp / 1000 + expiry
+  }
+}
+
+impl Signer for BitmexSigner {
+  fn headers(&self, payload: Payload<'_>, timestamp: i64) -> Result<HeaderMap> {
+    let expires = self.expires(timestamp);
+    let path = match payload.query {
+      "" => payload.path.to_string(),
+      query => format!("{}?{}", payload.path, query),
+    };
+    let signature = hmac_sha256(
+      &self.secret_key,
+      &format!("{}{}{}{}", payload.method, path, expires, payload.body),
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.append("api-expires", expires.into());
+    headers.append("api-key", header_value(&self.api_key)?);
+    headers.append("api-signature", header_value(&signature)?);
+    headers.append(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(headers)
+  }
+
+  fn realtime_auth(&self, timestamp: i64) -> RealtimeAuth {
+    let expires = self.expires(timestamp);
+    RealtimeAuth {
+      api_key: self.api_key.clone(),
+      expires,
+      signature: hmac_sha256(&self.secret_key, &format!("GET/realtime{}", expires)),
+    }
+  }
+
+  fn clock(&self) -> &Clock {
+    &self.clock
+  }
+}
+
+pub fn hmac_sha256(secret_key: &str, message: &str) -> String {
+  let mut mac =
+    Hmac::<Sha256>::new_from_slice(secret_key.as_bytes()).expect("hmac accepts keys of any size");
+  mac.update(message.as_bytes());
+  encode(mac.finalize().into_bytes())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+  value
+    .parse()
+    .map_err(|_| Error::Auth(format!("{:?} is not a valid header value", value)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // https://www.bitmex.com/app/apiKeysUsage
+  const BITMEX_API_KEY: &str = "LAqUlngMIQkIUjXMUreyu3qn";
+  const BITMEX_SECRET_KEY: &str = "chNOOS4KvNXR_Xq4k4c9qsfoKWvnDecLATCRlcBwyKDYnWgO";
+
+  // Bybit does not publish signatures, so the `bybit_*` tests are regression vectors taken
+  // from this signer. Each also signs the string Bybit's v5 docs spell out for the request,
+  // `timestamp + key + recv_window + params` or `GET/realtime + expires`, to pin the layout.
+  const BYBIT_API_KEY: &str = "XXXXXXXXXX";
+  const BYBIT_SECRET_KEY: &str = "YYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYYY";
+
+  fn bitmex_signature(method: Method, path: &str, query: &str, body: &str, expires: i64) -> String {
+    let signer = BitmexSigner::new(BITMEX_API_KEY, BITMEX_SECRET_KEY);
+    let payload = Payload {
+      method: &method,
+      path,
+      query,
+      body,
+    };
+    let headers = signer.headers(payload, (expires - 10) * 1000).unwrap();
+    assert_eq!(headers["api-expires"], expires.to_string().as_str());
+    assert_eq!(headers["api-key"], BITMEX_API_KEY);
+    headers["api-signature"].to_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn bitmex_get() {
+    assert_eq!(
+      bitmex_signature(Method::GET, "/api/v1/instrument", "", "", 1518064236),
+      "c7682d435d0cfe87c16098df34ef2eb5a549d4c5a3c2b1f0f77b8af73423bf00"
+    );
+  }
+
+  #[test]
+  fn bitmex_get_with_query() {
+    assert_eq!(
+      bitmex_signature(
+        Method::GET,
+        "/api/v1/instrument",
+        "filter=%7B%22symbol%22%3A+%22XBTM15%22%7D",
+        "",
+        1518064237
+      ),
+      "e2f422547eecb5b3cb29ade2127e21b858b235b386bfa45e1c1756eb3383919f"
+    );
+  }
+
+  #[test]
+  fn bitmex_post() {
+    assert_eq!(
+      bitmex_signature(
+        Method::POST,
+        "/api/v1/order",
+        "",
+        r#"{"symbol":"XBTM15","price":219.0,"clOrdID":"mm_bitmex_1a/oemUeQ4CAJZgP3fjHsA","orderQty":98}"#,
+        1518064238
+      ),
+      "1749cd2ccae4aa49048ae09f0b95110cee706e0944e6a14ad0b3a8cb45bd336b"
+    );
+  }
+
+  #[test]
+  fn bybit_get_regression() {
+    let signer = BybitSigner::new(BYBIT_API_KEY, BYBIT_SECRET_KEY);
+    let payload = Payload {
+      method: &Method::GET,
+      path: "/v5/order/realtime",
+      query: "category=option&symbol=BTC-29JUL22-25000-C",
+      body: "",
+    };
+    let headers = signer.headers(payload, 1658384314791).unwrap();
+    assert_eq!(headers["X-BAPI-API-KEY"], BYBIT_API_KEY);
+    assert_eq!(headers["X-BAPI-TIMESTAMP"], "1658384314791");
+    assert_eq!(headers["X-BAPI-RECV-WINDOW"], "5000");
+    assert_eq!(
+      headers["X-BAPI-SIGN"],
+      "49c26b0f76e0bec6b926f119814d66c4bdf59de44adf2a1864b1ea0bfd9c710c"
+    );
+    assert_eq!(
+      headers["X-BAPI-SIGN"],
+      hmac_sha256(
+        BYBIT_SECRET_KEY,
+        "1658384314791XXXXXXXXXX5000category=option&symbol=BTC-29JUL22-25000-C"
+      )
+      .as_str()
+    );
+  }
+
+  #[test]
+  fn bybit_post_regression() {
+    let signer = BybitSigner::new(BYBIT_API_KEY, BYBIT_SECRET_KEY);
+    let body = r#"{"category":"spot","symbol":"MATICUSDT","side":"Buy","orderType":"Limit","qty":"1.0","price":"0.7"}"#;
+    let payload = Payload {
+      method: &Method::POST,
+      path: "/v5/order/create",
+      query: "",
+      body,
+    };
+    let headers = signer.headers(payload, 1658385579423).unwrap();
+    assert_eq!(
+      headers["X-BAPI-SIGN"],
+      "6d3508263bb980b2f8f2419c6768558dd61cbc76005843e6fbf0e4a4026b47e3"
+    );
+    assert_eq!(
+      headers["X-BAPI-SIGN"],
+      hmac_sha256(
+        BYBIT_SECRET_KEY,
+        &format!("1658385579423XXXXXXXXXX5000{}", body)
+      )
+      .as_str()
+    );
+  }
+
+  #[test]
+  fn bybit_realtime_regression() {
+    let signer = BybitSigner::new(BYBIT_API_KEY, BYBIT_SECRET_KEY);
+    let auth = signer.realtime_auth(1703024689167 - 5000);
+    assert_eq!(auth.expires, 1703024689167);
+    assert_eq!(
+      auth.signature,
+      "e19dca2efdc278cf513b23d2eccf92124f80986e0313b710d3bb80f95501de6a"
+    );
+    assert_eq!(
+      auth.signature,
+      hmac_sha256(BYBIT_SECRET_KEY, "GET/realtime1703024689167")
+    );
+  }
+}
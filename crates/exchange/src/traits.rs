@@ -1,28 +1,180 @@
-use std::pin::Pin;
+use std::{pin::Pin, time::Duration};
 
+use async_trait::async_trait;
 use futures::stream::FusedStream;
 
-use crate::{order::{OrderBook, Request, OrderDelta}, pair::Pair};
+use crate::{
+  balance::Balance,
+  decimal::Quantity,
+  error::Result,
+  order::{Cancel, Fill, OrderAck, OrderBook, OrderUpdate, Request, Trade},
+  pair::Pair,
+  position::Position,
+  ws::Event,
+};
 
+pub type BoxStream<T> = Pin<Box<dyn FusedStream<Item = Result<T>> + Send>>;
+
+#[async_trait]
 pub trait PairGenerator {
-  fn get_pair(&self, symbol: &str) -> Pair;
+  /// Fetches the venue's instruments and caches them by symbol.
+  async fn load_pairs(&self) -> Result<()>;
+  /// A cached pair, `None` until `load_pairs` has seen `symbol`.
+  fn get_pair(&self, symbol: &str) -> Option<Pair>;
 }
 
 pub trait StreamFactory {
-  fn watch_order_book(&self) -> Pin<Box<dyn FusedStream<Item = OrderBook>>>;
-  fn watch_active_orders(&self) -> Pin<Box<dyn FusedStream<Item = OrderDelta>>>;
+  fn watch_order_book(&self, symbol: &str) -> BoxStream<Event<OrderBook>>;
+  /// Public trades of `symbol` as they happen. Trades missed while reconnecting are not
+  /// replayed.
+  fn watch_trades(&self, symbol: &str) -> BoxStream<Event<Trade>>;
+  /// Updates of the account's orders. Each one is also applied to the orders of the
+  /// matching [`Pair`] before it is yielded.
+  fn watch_active_orders(&self) -> BoxStream<Event<OrderUpdate>>;
+  /// Fills of the account's orders, with the fee charged for each.
+  fn watch_executions(&self) -> BoxStream<Event<Fill>>;
+  /// Changes to the account's wallet balances. Each one is also written to the cache read by
+  /// [`BalancesManager::get_balance`] before it is yielded.
+  fn watch_balances(&self) -> BoxStream<Event<Balance>>;
+  /// Changes to the account's derivatives positions. Each one is also written to the cache
+  /// read by [`PositionManager::get_position`] before it is yielded.
+  fn watch_positions(&self) -> BoxStream<Event<Position>>;
 }
 
-pub trait OrderManager {
-  fn submit_requests(&self, requests: Vec<Request>);
-  fn cancel_all_active_orders(&self);
+#[async_trait]
+pub trait OrderManager: Send + Sync {
+  /// Validates and submits every request, reporting one result per request in order.
+  /// Venues with a batch endpoint receive the requests in as few calls as possible.
+  async fn submit_requests(&self, requests: Vec<Request>) -> Vec<Result<OrderAck>>;
+  /// Cancels the given orders in batches, reporting the cancelled order id or an error per
+  /// cancel in order.
+  async fn cancel_orders(&self, cancels: Vec<Cancel>) -> Vec<Result<String>>;
+  /// Cancels every open order, returning the ids of the cancelled orders.
+  async fn cancel_all_active_orders(&self) -> Result<Vec<String>>;
+  /// Cancels every open order on `symbol`, returning the ids of the cancelled orders.
+  async fn cancel_all_for_symbol(&self, symbol: &str) -> Result<Vec<String>>;
+
+  /// Moves order `order_id` to the price and quantity of `request`. Venues that can amend
+  /// keep the order and its id, the default cancels it and submits `request` instead. The
+  /// ack names the order that is live afterwards.
+  async fn replace(&self, order_id: &str, request: Request) -> Result<OrderAck> {
+    cancel_and_submit(self, order_id, request).await
+  }
+}
+
+/// The fallback of [`OrderManager::replace`]: cancel `order_id`, then submit `request`.
+pub async fn cancel_and_submit<M: OrderManager + ?Sized>(
+  manager: &M,
+  order_id: &str,
+  request: Request,
+) -> Result<OrderAck> {
+  let cancel = Cancel::new(request.symbol.clone(), order_id);
+  for result in manager.cancel_orders(vec![cancel]).await {
+    result?;
+  }
+  manager
+    .submit_requests(vec![request])
+    .await
+    .pop()
+    .expect("one result per request")
+}
+
+/// A venue side timer that cancels every open order unless it is refreshed in time, so
+/// quotes do not outlive the process that placed them. Driven by a
+/// [`Heartbeat`](crate::heartbeat::Heartbeat).
+#[async_trait]
+pub trait DeadManSwitch: Send + Sync {
+  /// Arms the switch, or pushes it back if armed, to fire `timeout` from now.
+  async fn arm(&self, timeout: Duration) -> Result<()>;
+  /// Stops the switch. Open orders stay where the venue allows it, see the adapters.
+  async fn disarm(&self) -> Result<()>;
 }
 
 pub trait FromApi {
   fn from_api(api_url: &str, api_wss_url: &str, api_key: &str, secret_key: &str) -> Self;
 }
 
+#[async_trait]
 pub trait BalancesManager {
-  fn load_all_balances(&self);
-  fn get_balance(&self, ticker: &str) -> Option<f64>;
+  /// Fetches every wallet balance, replacing the cached ones.
+  async fn load_all_balances(&self) -> Result<()>;
+  /// A cached balance, kept current by [`StreamFactory::watch_balances`].
+  fn get_balance(&self, ticker: &str) -> Option<Quantity>;
+}
+
+#[async_trait]
+pub trait PositionManager {
+  /// Fetches every open position, replacing the cached ones.
+  async fn load_positions(&self) -> Result<()>;
+  /// A cached position, kept current by [`StreamFactory::watch_positions`]. `None` for
+  /// symbols that have not been traded since loading.
+  fn get_position(&self, symbol: &str) -> Option<Position>;
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use super::*;
+  use crate::{error::Error, order::Side};
+
+  /// Records calls, failing cancels of order `"gone"`.
+  #[derive(Default)]
+  struct Recorder {
+    calls: Mutex<Vec<String>>,
+  }
+
+  #[async_trait]
+  impl OrderManager for Recorder {
+    async fn submit_requests(&self, requests: Vec<Request>) -> Vec<Result<OrderAck>> {
+      let mut calls = self.calls.lock().unwrap();
+      requests
+        .into_iter()
+        .map(|request| {
+          calls.push(format!("submit {}", request.client_order_id));
+          Ok(OrderAck {
+            order_id: request.client_order_id,
+            exchange_order_id: "b1c9".to_string(),
+          })
+        })
+        .collect()
+    }
+
+    async fn cancel_orders(&self, cancels: Vec<Cancel>) -> Vec<Result<String>> {
+      let mut calls = self.calls.lock().unwrap();
+      cancels
+        .into_iter()
+        .map(|cancel| {
+          calls.push(format!("cancel {}", cancel.order_id()));
+          match cancel.order_id() {
+            "gone" => Err(Error::InvalidRequest("gone".to_string())),
+            id => Ok(id.to_string()),
+          }
+        })
+        .collect()
+    }
+
+    async fn cancel_all_active_orders(&self) -> Result<Vec<String>> {
+      Ok(Vec::new())
+    }
+
+    async fn cancel_all_for_symbol(&self, _symbol: &str) -> Result<Vec<String>> {
+      Ok(Vec::new())
+    }
+  }
+
+  #[tokio::test]
+  async fn replace_cancels_then_submits() {
+    let manager = Recorder::default();
+    let mut request = Request::market("MATICUSDT", Side::Buy, 10.into());
+    request.client_order_id = "2".to_string();
+    let ack = manager.replace("1", request.clone()).await.unwrap();
+    assert_eq!(ack.order_id, "2");
+    assert_eq!(*manager.calls.lock().unwrap(), ["cancel 1", "submit 2"]);
+
+    // A failed cancel leaves the old order alone and places nothing.
+    let manager = Recorder::default();
+    assert!(manager.replace("gone", request).await.is_err());
+    assert_eq!(*manager.calls.lock().unwrap(), ["cancel gone"]);
+  }
 }
@@ -0,0 +1,270 @@
+use std::time::Duration;
+
+use futures::{future, stream, SinkExt, Stream, StreamExt, TryStreamExt};
+use tokio::{
+  net::TcpStream,
+  time::{interval_at, sleep, Instant, Interval, MissedTickBehavior},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use crate::error::{Error, Result};
+
+type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<T> {
+  Message(T),
+  /// The first connection was established and its subscriptions sent, emitted once per
+  /// stream. The venue may not have acknowledged them yet, so a snapshot fetched right away
+  /// can still miss an update made in between.
+  Connected,
+  /// The connection was re-established and subscriptions replayed. Anything cached from
+  /// earlier messages may be stale.
+  Reconnected,
+}
+
+impl<T> Event<T> {
+  pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Event<U> {
+    match self {
+      Event::Message(message) => Event::Message(f(message)),
+      Event::Connected => Event::Connected,
+      Event::Reconnected => Event::Reconnected,
+    }
+  }
+}
+
+/// Venue specific framing of a websocket session.
+pub trait Protocol: Send + Sync + 'static {
+  /// Sent after every (re)connect, before subscribing, so credentials get a fresh expiry.
+  fn login(&self) -> Result<Option<Message>>;
+  fn subscribe(&self, topics: &[String]) -> Result<Message>;
+  /// Application level keep-alive, sent every `ping_interval`. A connection that stays
+  /// silent for two intervals is considered dead.
+  fn ping(&self) -> Result<Message>;
+  fn ping_interval(&self) -> Duration;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+  pub initial: Duration,
+  pub max: Duration,
+}
+
+impl Backoff {
+  pub fn delay(&self, attempt: u32) -> Duration {
+    self
+      .initial
+      .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+      .min(self.max)
+  }
+}
+
+impl Default for Backoff {
+  fn default() -> Self {
+    Self {
+      initial: Duration::from_millis(250),
+      max: Duration::from_secs(30),
+    }
+  }
+}
+
+/// A websocket connection that reconnects with exponential backoff, logs in again and
+/// replays its subscriptions whenever the connection drops or goes quiet.
+pub struct Session<P> {
+  url: String,
+  protocol: P,
+  topics: Vec<String>,
+  backoff: Backoff,
+}
+
+impl<P: Protocol> Session<P> {
+  pub fn new(url: impl Into<String>, protocol: P, topics: Vec<String>) -> Self {
+    Self {
+      url: url.into(),
+      protocol,
+      topics,
+      backoff: Backoff::default(),
+    }
+  }
+
+  pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+    self.backoff = backoff;
+    self
+  }
+
+  async fn connect(&self) -> Result<Ws> {
+    let url =
+      Url::parse(&self.url).map_err(|e| Error::InvalidRequest(format!("{}: {}", self.url, e)))?;
+    let (mut ws, _) = connect_async(url).await?;
+    if let Some(login) = self.protocol.login()? {
+      ws.send(login).await?;
+    }
+    if !self.topics.is_empty() {
+      ws.send(self.protocol.subscribe(&self.topics)?).await?;
+    }
+    tracing::info!("connected to {}, subscribed to {:?}", self.url, self.topics);
+    Ok(ws)
+  }
+
+  /// Text frames received by the session. Connection failures are yielded as errors
+  /// without ending the stream.
+  pub fn into_stream(self) -> impl Stream<Item = Result<Event<String>>> + Send {
+    let state = State {
+      ping: ping_timer(self.protocol.ping_interval()),
+      session: self,
+      ws: None,
+      attempt: 0,
+      connected: false,
+      last_message: Instant::now(),
+    };
+
+    stream::unfold(state, |mut state| async move {
+      let item = state.next().await;
+      Some((item, state))
+    })
+  }
+}
+
+struct State<P> {
+  session: Session<P>,
+  ws: Option<Ws>,
+  attempt: u32,
+  connected: bool,
+  ping: Interval,
+  last_message: Instant,
+}
+
+impl<P: Protocol> State<P> {
+  async fn next(&mut self) -> Result<Event<String>> {
+    loop {
+      let Some(ws) = self.ws.as_mut() else {
+        // Only a connection that delivered data resets the backoff, so a venue that
+        // accepts and immediately drops us is not hammered.
+        if self.attempt > 0 {
+          sleep(self.session.backoff.delay(self.attempt)).await;
+        }
+        self.attempt += 1;
+        let ws = self.session.connect().await?;
+        self.ws = Some(ws);
+        self.last_message = Instant::now();
+        self.ping.reset();
+        return match std::mem::replace(&mut self.connected, true) {
+          true => Ok(Event::Reconnected),
+          false => Ok(Event::Connected),
+        };
+      };
+
+      tokio::select! {
+        message = ws.next() => {
+          self.last_message = Instant::now();
+          match message {
+            Some(Ok(Message::Text(text))) => {
+              self.attempt = 0;
+              return Ok(Event::Message(text));
+            }
+            Some(Ok(Message::Ping(payload))) => {
+              if let Err(e) = ws.send(Message::Pong(payload)).await {
+                return Err(self.disconnect(e.into()));
+              }
+            }
+            Some(Ok(Message::Close(frame))) => {
+              tracing::warn!("{} closed: {:?}", self.session.url, frame);
+              self.ws = None;
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(self.disconnect(e.into())),
+            None => {
+              tracing::warn!("{} ended", self.session.url);
+              self.ws = None;
+            }
+          }
+        }
+        _ = self.ping.tick() => {
+          let interval = self.session.protocol.ping_interval();
+          if self.last_message.elapsed() > interval * 2 {
+            tracing::warn!("{} went quiet, reconnecting", self.session.url);
+            self.ws = None;
+            continue;
+          }
+          let ping = self.session.protocol.ping()?;
+          if let Err(e) = ws.send(ping).await {
+            return Err(self.disconnect(e.into()));
+          }
+        }
+      }
+    }
+  }
+
+  fn disconnect(&mut self, error: Error) -> Error {
+    tracing::warn!("{} failed: {}", self.session.url, error);
+    self.ws = None;
+    error
+  }
+}
+
+/// Flattens every message of `events` into the rows `rows` makes of it, passing connection
+/// events through.
+pub(crate) fn flat_messages<M, T: Send>(
+  events: impl Stream<Item = Result<Event<M>>> + Send,
+  mut rows: impl FnMut(M) -> Vec<Result<T>> + Send,
+) -> impl Stream<Item = Result<Event<T>>> + Send {
+  events
+    .map_ok(move |event| match event {
+      Event::Message(message) => {
+        stream::iter(rows(message).into_iter().map(|row| row.map(Event::Message))).left_stream()
+      }
+      event => stream::once(future::ready(Ok(event.map(|_| unreachable!())))).right_stream(),
+    })
+    .try_flatten()
+}
+
+/// Ends `stream` after an [`Error::Subscription`], since a refused topic stays silent while
+/// pongs keep its session alive.
+pub(crate) fn until_refused<T: Send>(
+  stream: impl Stream<Item = Result<T>> + Send,
+) -> impl Stream<Item = Result<T>> + Send {
+  stream.scan(false, |refused, item| {
+    if *refused {
+      return future::ready(None);
+    }
+    *refused = matches!(item, Err(Error::Subscription(_)));
+    future::ready(Some(item))
+  })
+}
+
+fn ping_timer(period: Duration) -> Interval {
+  let mut ping = interval_at(Instant::now() + period, period);
+  ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  ping
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backoff() {
+    let backoff = Backoff {
+      initial: Duration::from_millis(100),
+      max: Duration::from_secs(1),
+    };
+    assert_eq!(backoff.delay(1), Duration::from_millis(100));
+    assert_eq!(backoff.delay(2), Duration::from_millis(200));
+    assert_eq!(backoff.delay(4), Duration::from_millis(800));
+    assert_eq!(backoff.delay(5), Duration::from_secs(1));
+    assert_eq!(backoff.delay(64), Duration::from_secs(1));
+  }
+
+  #[tokio::test]
+  async fn until_refused_ends_the_stream() {
+    let items = stream::iter(vec![
+      Ok(1),
+      Err(Error::Subscription("unknown topic".to_string())),
+      Ok(2),
+    ]);
+    let items = until_refused(items).collect::<Vec<_>>().await;
+    assert_eq!(items.len(), 2);
+    assert!(matches!(items[1], Err(Error::Subscription(_))));
+  }
+}